@@ -1,48 +1,277 @@
-use crate::game::GameState;
-use crate::ui::{UiData, UiView};
+use crate::game::{GameAction, GameState, Projectile};
+use crate::replay::{Recorder, Replay, ReplaySpeed};
+use crate::save;
+use crate::ui::{InventoryData, InventoryView, LevelUpView, UiData, UiView};
 use crate::visibility::{CellVisibility, VisibilityAlgorithm};
-use crate::world::{ItemType, Layer, NpcType, Tile};
+use crate::world::{ItemType, Layer, LevelUpChoice, NpcType, Tile};
 use chargrid::{
     app::{App as ChargridApp, ControlFlow},
     input::{keys, Input, KeyboardInput},
-    render::{ColModify, Frame, View, ViewCell, ViewContext},
+    render::{Buffer, ColModify, Frame, View, ViewCell, ViewContext},
 };
 use coord_2d::{Coord, Size};
 use direction::CardinalDirection;
+use line_2d::LineSegment;
 use rgb24::Rgb24;
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
 use std::time::Duration;
 
 const UI_NUM_ROWS: u32 = 5;
 
+// How long projectiles stay on screen after being fired
+const PROJECTILE_DISPLAY_DURATION: Duration = Duration::from_millis(250);
+
+// The part of the screen not taken up by the UI, in which the game is drawn
+pub fn game_area_size(screen_size: Size) -> Size {
+    screen_size.set_height(screen_size.height() - UI_NUM_ROWS)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InventoryAction {
+    Use,
+    Drop,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum InputMode {
+    Game,
+    Inventory(InventoryAction),
+    LevelUp,
+}
+
+impl InputMode {
+    // The player must choose how to level up before doing anything else
+    fn for_game_state(game_state: &GameState) -> Self {
+        if game_state.has_pending_level_up() {
+            Self::LevelUp
+        } else {
+            Self::Game
+        }
+    }
+}
+
+// Errors that happen while the game is running, held back to be reported once the frontend has
+// exited. The terminal frontend owns the screen while it runs, so anything printed in the meantime
+// would be drawn over or lost.
+#[derive(Clone, Default)]
+pub struct ErrorLog {
+    errors: Rc<RefCell<Vec<String>>>,
+}
+
+impl ErrorLog {
+    fn push(&self, error: String) {
+        self.errors.borrow_mut().push(error);
+    }
+
+    pub fn report(&self) {
+        for error in self.errors.borrow().iter() {
+            eprintln!("{}", error);
+        }
+    }
+}
+
+// A recorded game being played back
+struct ReplayState {
+    replay: Replay,
+    speed: ReplaySpeed,
+    until_next_action: Duration,
+}
+
 struct AppData {
     game_state: GameState,
     visibility_algorithm: VisibilityAlgorithm,
+    input_mode: InputMode,
+    save_file: Option<PathBuf>,
+    recorder: Option<Recorder>,
+    replay_state: Option<ReplayState>,
+    until_projectiles_disappear: Duration,
+    error_log: Option<ErrorLog>,
 }
 
 impl AppData {
-    fn new(screen_size: Size, rng_seed: u64, visibility_algorithm: VisibilityAlgorithm) -> Self {
-        let game_area_size = screen_size.set_height(screen_size.height() - UI_NUM_ROWS);
+    fn new(
+        game_state: GameState,
+        visibility_algorithm: VisibilityAlgorithm,
+        save_file: Option<PathBuf>,
+        recorder: Option<Recorder>,
+        replay: Option<(Replay, ReplaySpeed)>,
+        error_log: Option<ErrorLog>,
+    ) -> Self {
+        // A saved game may have been saved while a level up was waiting to be chosen
+        let input_mode = InputMode::for_game_state(&game_state);
         Self {
-            game_state: GameState::new(game_area_size, rng_seed, visibility_algorithm),
+            game_state,
             visibility_algorithm,
+            input_mode,
+            save_file,
+            recorder,
+            replay_state: replay.map(|(replay, speed)| ReplayState {
+                replay,
+                speed,
+                until_next_action: Duration::from_secs(0),
+            }),
+            until_projectiles_disappear: Duration::from_secs(0),
+            error_log,
+        }
+    }
+    // Errors are added to the error log if there is one, and otherwise printed straight away
+    fn report_error(&self, error: String) {
+        match self.error_log.as_ref() {
+            Some(error_log) => error_log.push(error),
+            None => eprintln!("{}", error),
+        }
+    }
+    // Saves the game so it can be resumed, unless the player has died in which case any
+    // existing save is deleted
+    fn save_or_delete(&self) {
+        let save_file = match self.save_file.as_ref() {
+            Some(save_file) => save_file,
+            None => return,
+        };
+        let result = if self.game_state.is_player_alive() {
+            save::save_game(&self.game_state, save_file)
+        } else {
+            save::delete_save(save_file)
+        };
+        if let Err(error) = result {
+            self.report_error(format!(
+                "Failed to save game to {}: {}",
+                save_file.display(),
+                error
+            ));
+        }
+    }
+    fn perform_action(&mut self, action: GameAction) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            if let Err(error) = recorder.record(action) {
+                self.report_error(format!(
+                    "Failed to record action, stopping recording: {}",
+                    error
+                ));
+                self.recorder = None;
+            }
+        }
+        self.game_state.perform_action(action);
+        self.input_mode = InputMode::for_game_state(&self.game_state);
+        self.game_state.update_visibility(self.visibility_algorithm);
+        if !self.game_state.projectiles().is_empty() {
+            self.until_projectiles_disappear = PROJECTILE_DISPLAY_DURATION;
+        }
+    }
+    fn perform_next_replay_action(&mut self) {
+        let next_action = self
+            .replay_state
+            .as_mut()
+            .and_then(|replay_state| replay_state.replay.next_action());
+        if let Some(action) = next_action {
+            self.perform_action(action);
+        }
+        if self
+            .replay_state
+            .as_ref()
+            .map(|replay_state| replay_state.replay.is_finished())
+            .unwrap_or(false)
+        {
+            // Once the replay is over the player may continue the game
+            self.replay_state = None;
+        }
+    }
+    fn tick(&mut self, since_last_frame: Duration) {
+        self.until_projectiles_disappear = self
+            .until_projectiles_disappear
+            .saturating_sub(since_last_frame);
+        if let Some(replay_state) = self.replay_state.as_mut() {
+            if let ReplaySpeed::Delay(delay) = replay_state.speed {
+                if let Some(remaining) =
+                    replay_state.until_next_action.checked_sub(since_last_frame)
+                {
+                    replay_state.until_next_action = remaining;
+                } else {
+                    replay_state.until_next_action = delay;
+                    self.perform_next_replay_action();
+                }
+            }
         }
     }
     fn handle_input(&mut self, input: Input) {
         if !self.game_state.is_player_alive() {
             return;
         }
-        match input {
-            Input::Keyboard(key) => match key {
-                KeyboardInput::Left => self.game_state.maybe_move_player(CardinalDirection::West),
-                KeyboardInput::Right => self.game_state.maybe_move_player(CardinalDirection::East),
-                KeyboardInput::Up => self.game_state.maybe_move_player(CardinalDirection::North),
-                KeyboardInput::Down => self.game_state.maybe_move_player(CardinalDirection::South),
-                KeyboardInput::Char(' ') => self.game_state.wait_player(),
-                _ => (),
-            },
-            _ => (),
+        if let Input::Keyboard(key) = input {
+            if let Some(replay_state) = self.replay_state.as_ref() {
+                // Player input is ignored during a replay, except to step through it
+                if let ReplaySpeed::Step = replay_state.speed {
+                    self.perform_next_replay_action();
+                }
+                return;
+            }
+            let action = match self.input_mode {
+                InputMode::Game => self.handle_game_input(key),
+                InputMode::Inventory(action) => self.handle_inventory_input(action, key),
+                InputMode::LevelUp => self.handle_level_up_input(key),
+            };
+            if let Some(action) = action {
+                self.perform_action(action);
+            }
+        }
+    }
+    fn handle_game_input(&mut self, key: KeyboardInput) -> Option<GameAction> {
+        let action = match key {
+            KeyboardInput::Left => GameAction::Move(CardinalDirection::West),
+            KeyboardInput::Right => GameAction::Move(CardinalDirection::East),
+            KeyboardInput::Up => GameAction::Move(CardinalDirection::North),
+            KeyboardInput::Down => GameAction::Move(CardinalDirection::South),
+            KeyboardInput::Char(' ') => GameAction::Wait,
+            KeyboardInput::Char('g') | KeyboardInput::Char(',') => GameAction::GetItem,
+            KeyboardInput::Char('q') => {
+                let health_potion_index = self
+                    .game_state
+                    .player_inventory_item_types()
+                    .position(|item_type| item_type == Some(ItemType::HealthPotion))?;
+                GameAction::UseItem(health_potion_index)
+            }
+            KeyboardInput::Char('>') => GameAction::Descend,
+            KeyboardInput::Char('i') => {
+                self.input_mode = InputMode::Inventory(InventoryAction::Use);
+                return None;
+            }
+            KeyboardInput::Char('d') => {
+                self.input_mode = InputMode::Inventory(InventoryAction::Drop);
+                return None;
+            }
+            _ => return None,
+        };
+        Some(action)
+    }
+    fn handle_inventory_input(
+        &mut self,
+        action: InventoryAction,
+        key: KeyboardInput,
+    ) -> Option<GameAction> {
+        match key {
+            keys::ESCAPE => {
+                self.input_mode = InputMode::Game;
+                None
+            }
+            KeyboardInput::Char(ch @ 'a'..='z') => {
+                let inventory_index = (ch as u8 - b'a') as usize;
+                self.input_mode = InputMode::Game;
+                match action {
+                    InventoryAction::Use => Some(GameAction::UseItem(inventory_index)),
+                    InventoryAction::Drop => Some(GameAction::DropItem(inventory_index)),
+                }
+            }
+            _ => None,
         }
-        self.game_state.update_visibility(self.visibility_algorithm);
+    }
+    fn handle_level_up_input(&mut self, key: KeyboardInput) -> Option<GameAction> {
+        if let KeyboardInput::Char(ch @ 'a'..='z') = key {
+            let &choice = LevelUpChoice::ALL.get((ch as u8 - b'a') as usize)?;
+            return Some(GameAction::LevelUp(choice));
+        }
+        None
     }
 }
 
@@ -50,6 +279,9 @@ struct AppView {
     ui_y_offset: i32,
     game_view: GameView,
     ui_view: UiView,
+    inventory_view: InventoryView,
+    level_up_view: LevelUpView,
+    inventory_item_types: Vec<Option<ItemType>>,
 }
 
 impl AppView {
@@ -60,8 +292,32 @@ impl AppView {
             ui_y_offset,
             game_view: GameView::default(),
             ui_view: UiView::default(),
+            inventory_view: InventoryView::default(),
+            level_up_view: LevelUpView::default(),
+            inventory_item_types: Vec::new(),
         }
     }
+    fn view_ui<F: Frame, C: ColModify>(
+        &mut self,
+        game_state: &GameState,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) {
+        let player_hit_points = game_state.player_hit_points();
+        let player_experience = game_state.player_experience();
+        let dungeon_level = game_state.dungeon_level();
+        let messages = game_state.message_log();
+        self.ui_view.view(
+            UiData {
+                player_hit_points,
+                player_experience,
+                dungeon_level,
+                messages,
+            },
+            context.add_offset(Coord::new(0, self.ui_y_offset)),
+            frame,
+        );
+    }
 }
 
 pub mod colours {
@@ -70,12 +326,27 @@ pub mod colours {
     pub const PLAYER: Rgb24 = Rgb24::new_grey(255);
     pub const ORC: Rgb24 = Rgb24::new(0, 187, 0);
     pub const TROLL: Rgb24 = Rgb24::new(187, 0, 0);
+    pub const ARCHER: Rgb24 = Rgb24::new(63, 127, 255);
     pub const HEALTH_POTION: Rgb24 = Rgb24::new(255, 0, 255);
+    pub const STAIRS: Rgb24 = Rgb24::new(255, 255, 0);
+    pub const DOOR: Rgb24 = Rgb24::new(187, 127, 63);
+    pub const TALL_GRASS: Rgb24 = Rgb24::new(63, 187, 63);
+    pub const SMOKE: Rgb24 = Rgb24::new_grey(187);
+    pub const GLASS_WALL: Rgb24 = Rgb24::new(127, 223, 255);
+    pub const TORCH: Rgb24 = Rgb24::new(255, 187, 0);
+    pub const PROJECTILE: Rgb24 = Rgb24::new_grey(255);
 
     pub fn npc_colour(npc_type: NpcType) -> Rgb24 {
         match npc_type {
             NpcType::Orc => ORC,
             NpcType::Troll => TROLL,
+            NpcType::Archer => ARCHER,
+        }
+    }
+
+    pub fn item_colour(item_type: ItemType) -> Rgb24 {
+        match item_type {
+            ItemType::HealthPotion => HEALTH_POTION,
         }
     }
 }
@@ -96,6 +367,11 @@ fn currently_visible_view_cell_of_tile(tile: Tile) -> ViewCell {
             .with_character('#')
             .with_foreground(Rgb24::new(0, 63, 63))
             .with_background(Rgb24::new(63, 127, 127)),
+        Tile::Stairs => ViewCell::new()
+            .with_character('>')
+            .with_bold(true)
+            .with_foreground(colours::STAIRS)
+            .with_background(Rgb24::new(0, 0, 63)),
         Tile::Npc(NpcType::Orc) => ViewCell::new()
             .with_character('o')
             .with_bold(true)
@@ -104,6 +380,10 @@ fn currently_visible_view_cell_of_tile(tile: Tile) -> ViewCell {
             .with_character('T')
             .with_bold(true)
             .with_foreground(colours::TROLL),
+        Tile::Npc(NpcType::Archer) => ViewCell::new()
+            .with_character('a')
+            .with_bold(true)
+            .with_foreground(colours::ARCHER),
         Tile::NpcCorpse(NpcType::Orc) => ViewCell::new()
             .with_character('%')
             .with_bold(true)
@@ -112,12 +392,59 @@ fn currently_visible_view_cell_of_tile(tile: Tile) -> ViewCell {
             .with_character('%')
             .with_bold(true)
             .with_foreground(colours::TROLL),
+        Tile::NpcCorpse(NpcType::Archer) => ViewCell::new()
+            .with_character('%')
+            .with_bold(true)
+            .with_foreground(colours::ARCHER),
         Tile::Item(ItemType::HealthPotion) => ViewCell::new()
             .with_character('!')
             .with_foreground(colours::HEALTH_POTION),
+        Tile::ClosedDoor => ViewCell::new()
+            .with_character('+')
+            .with_bold(true)
+            .with_foreground(colours::DOOR)
+            .with_background(Rgb24::new(0, 0, 63)),
+        Tile::OpenDoor => ViewCell::new()
+            .with_character('\'')
+            .with_bold(true)
+            .with_foreground(colours::DOOR)
+            .with_background(Rgb24::new(0, 0, 63)),
+        Tile::TallGrass => ViewCell::new()
+            .with_character('"')
+            .with_foreground(colours::TALL_GRASS)
+            .with_background(Rgb24::new(0, 0, 63)),
+        Tile::Smoke => ViewCell::new()
+            .with_character('~')
+            .with_foreground(colours::SMOKE)
+            .with_background(Rgb24::new(0, 0, 63)),
+        Tile::GlassWall => ViewCell::new()
+            .with_character('=')
+            .with_foreground(colours::GLASS_WALL)
+            .with_background(Rgb24::new(63, 127, 127)),
+        Tile::Torch => ViewCell::new()
+            .with_character('*')
+            .with_bold(true)
+            .with_foreground(colours::TORCH)
+            .with_background(Rgb24::new(63, 127, 127)),
     }
 }
 
+// Visible cells are drawn in the colour of the light falling on them, and dimmer the less light
+// there is. No channel of the light is treated as dimmer than this, so that dimly lit cells are
+// still legible.
+const MIN_RENDERED_LIGHT: u8 = 95;
+
+fn apply_light(mut view_cell: ViewCell, light: Rgb24) -> ViewCell {
+    let light = light.floor(MIN_RENDERED_LIGHT);
+    let style = &mut view_cell.style;
+    style.foreground = style.foreground.map(|colour| colour.normalised_mul(light));
+    style.background = style.background.map(|colour| colour.normalised_mul(light));
+    view_cell
+}
+
+// How bright remembered objects are compared to when they are visible, out of 255
+const REMEMBERED_OBJECT_BRIGHTNESS: u8 = 95;
+
 fn previously_visible_view_cell_of_tile(tile: Tile) -> ViewCell {
     match tile {
         Tile::Floor => ViewCell::new()
@@ -128,12 +455,68 @@ fn previously_visible_view_cell_of_tile(tile: Tile) -> ViewCell {
             .with_character('#')
             .with_foreground(Rgb24::new_grey(63))
             .with_background(Rgb24::new_grey(0)),
-        _ => ViewCell::new(),
+        Tile::Stairs => ViewCell::new()
+            .with_character('>')
+            .with_bold(true)
+            .with_foreground(Rgb24::new_grey(63))
+            .with_background(Rgb24::new_grey(0)),
+        Tile::ClosedDoor => ViewCell::new()
+            .with_character('+')
+            .with_foreground(Rgb24::new_grey(63))
+            .with_background(Rgb24::new_grey(0)),
+        Tile::OpenDoor => ViewCell::new()
+            .with_character('\'')
+            .with_foreground(Rgb24::new_grey(63))
+            .with_background(Rgb24::new_grey(0)),
+        Tile::TallGrass => ViewCell::new()
+            .with_character('"')
+            .with_foreground(Rgb24::new_grey(63))
+            .with_background(Rgb24::new_grey(0)),
+        Tile::Smoke => ViewCell::new()
+            .with_character('~')
+            .with_foreground(Rgb24::new_grey(63))
+            .with_background(Rgb24::new_grey(0)),
+        Tile::GlassWall => ViewCell::new()
+            .with_character('=')
+            .with_foreground(Rgb24::new_grey(63))
+            .with_background(Rgb24::new_grey(0)),
+        Tile::Torch => ViewCell::new()
+            .with_character('*')
+            .with_foreground(Rgb24::new_grey(63))
+            .with_background(Rgb24::new_grey(0)),
+        // Objects keep a dim version of their colour, so different kinds can be told apart
+        Tile::PlayerCorpse => ViewCell::new()
+            .with_character('%')
+            .with_foreground(colours::PLAYER.normalised_scalar_mul(REMEMBERED_OBJECT_BRIGHTNESS)),
+        Tile::NpcCorpse(npc_type) => ViewCell::new().with_character('%').with_foreground(
+            colours::npc_colour(npc_type).normalised_scalar_mul(REMEMBERED_OBJECT_BRIGHTNESS),
+        ),
+        Tile::Item(item_type) => ViewCell::new().with_character('!').with_foreground(
+            colours::item_colour(item_type).normalised_scalar_mul(REMEMBERED_OBJECT_BRIGHTNESS),
+        ),
+        // Characters are never remembered
+        Tile::Player | Tile::Npc(_) => ViewCell::new(),
+    }
+}
+
+// Chooses a character that points along the path of a projectile
+fn projectile_character(projectile: Projectile) -> char {
+    let delta = projectile.dst - projectile.src;
+    if delta.x.abs() > delta.y.abs() * 2 {
+        '-'
+    } else if delta.y.abs() > delta.x.abs() * 2 {
+        '|'
+    } else if (delta.x > 0) == (delta.y > 0) {
+        '\\'
+    } else {
+        '/'
     }
 }
 
 #[derive(Default)]
-struct GameView {}
+struct GameView {
+    show_projectiles: bool,
+}
 
 impl<'a> View<&'a GameState> for GameView {
     fn view<F: Frame, C: ColModify>(
@@ -144,9 +527,10 @@ impl<'a> View<&'a GameState> for GameView {
     ) {
         for entity_to_render in game_state.entities_to_render() {
             let view_cell = match entity_to_render.visibility {
-                CellVisibility::Currently => {
-                    currently_visible_view_cell_of_tile(entity_to_render.tile)
-                }
+                CellVisibility::Currently => apply_light(
+                    currently_visible_view_cell_of_tile(entity_to_render.tile),
+                    entity_to_render.light,
+                ),
                 CellVisibility::Previously => {
                     previously_visible_view_cell_of_tile(entity_to_render.tile)
                 }
@@ -161,6 +545,26 @@ impl<'a> View<&'a GameState> for GameView {
             };
             frame.set_cell_relative(entity_to_render.location.coord, depth, view_cell, context);
         }
+        if !self.show_projectiles {
+            return;
+        }
+        // Projectiles are drawn over everything between the cells they were fired from and hit,
+        // but only where the player can see
+        const PROJECTILE_DEPTH: i8 = 4;
+        for &projectile in game_state.projectiles() {
+            let view_cell = ViewCell::new()
+                .with_character(projectile_character(projectile))
+                .with_bold(true)
+                .with_foreground(colours::PROJECTILE);
+            for coord in LineSegment::new(projectile.src, projectile.dst).iter() {
+                if coord == projectile.src || coord == projectile.dst {
+                    continue;
+                }
+                if game_state.cell_visibility(coord) == CellVisibility::Currently {
+                    frame.set_cell_relative(coord, PROJECTILE_DEPTH, view_cell, context);
+                }
+            }
+        }
     }
 }
 
@@ -171,18 +575,44 @@ impl<'a> View<&'a AppData> for AppView {
         context: ViewContext<C>,
         frame: &mut F,
     ) {
-        self.game_view.view(&data.game_state, context, frame);
-        let player_hit_points = data.game_state.player_hit_points();
-        let messages = data.game_state.message_log();
-        self.ui_view.view(
-            UiData {
-                player_hit_points,
-                messages,
-            },
-            context.add_offset(Coord::new(0, self.ui_y_offset)),
-            frame,
-        );
+        self.game_view.show_projectiles = !data.until_projectiles_disappear.is_zero();
+        match data.input_mode {
+            InputMode::Game => self.game_view.view(&data.game_state, context, frame),
+            InputMode::Inventory(action) => {
+                self.inventory_item_types.clear();
+                self.inventory_item_types
+                    .extend(data.game_state.player_inventory_item_types());
+                self.inventory_view.view(
+                    InventoryData {
+                        action,
+                        item_types: &self.inventory_item_types,
+                    },
+                    context,
+                    frame,
+                );
+            }
+            InputMode::LevelUp => self.level_up_view.view((), context, frame),
+        }
+        self.view_ui(&data.game_state, context, frame);
+    }
+}
+
+// Draws the game screen as it appears during play, keeping only the characters of each cell.
+// Each row becomes a line of text with trailing spaces removed. This is used to compare what
+// the player sees against expected output in tests.
+pub fn render_text(game_state: &GameState, screen_size: Size) -> String {
+    let mut view = AppView::new(screen_size);
+    let mut buffer = Buffer::new(screen_size);
+    let context = ViewContext::default_with_size(screen_size);
+    view.game_view.view(game_state, context, &mut buffer);
+    view.view_ui(game_state, context, &mut buffer);
+    let mut text = String::new();
+    for row in buffer.rows() {
+        let line = row.iter().map(|cell| cell.character).collect::<String>();
+        text.push_str(line.trim_end());
+        text.push('\n');
     }
+    text
 }
 
 pub struct App {
@@ -193,11 +623,22 @@ pub struct App {
 impl App {
     pub fn new(
         screen_size: Size,
-        rng_seed: u64,
+        game_state: GameState,
         visibility_algorithm: VisibilityAlgorithm,
+        save_file: Option<PathBuf>,
+        recorder: Option<Recorder>,
+        replay: Option<(Replay, ReplaySpeed)>,
+        error_log: Option<ErrorLog>,
     ) -> Self {
         Self {
-            data: AppData::new(screen_size, rng_seed, visibility_algorithm),
+            data: AppData::new(
+                game_state,
+                visibility_algorithm,
+                save_file,
+                recorder,
+                replay,
+                error_log,
+            ),
             view: AppView::new(screen_size),
         }
     }
@@ -206,7 +647,14 @@ impl App {
 impl ChargridApp for App {
     fn on_input(&mut self, input: Input) -> Option<ControlFlow> {
         match input {
-            Input::Keyboard(keys::ETX) | Input::Keyboard(keys::ESCAPE) => Some(ControlFlow::Exit),
+            Input::Keyboard(keys::ETX) => {
+                self.data.save_or_delete();
+                Some(ControlFlow::Exit)
+            }
+            Input::Keyboard(keys::ESCAPE) if self.data.input_mode == InputMode::Game => {
+                self.data.save_or_delete();
+                Some(ControlFlow::Exit)
+            }
             other => {
                 self.data.handle_input(other);
                 None
@@ -215,7 +663,7 @@ impl ChargridApp for App {
     }
     fn on_frame<F, C>(
         &mut self,
-        _since_last_frame: Duration,
+        since_last_frame: Duration,
         view_context: ViewContext<C>,
         frame: &mut F,
     ) -> Option<ControlFlow>
@@ -223,6 +671,7 @@ impl ChargridApp for App {
         F: Frame,
         C: ColModify,
     {
+        self.data.tick(since_last_frame);
         self.view.view(&self.data, view_context, frame);
         None
     }
@@ -1,4 +1,5 @@
-use crate::world::World;
+use crate::visibility::VISION_DISTANCE;
+use crate::world::{NpcType, World};
 use coord_2d::{Coord, Size};
 use direction::CardinalDirection;
 use entity_table::Entity;
@@ -7,23 +8,42 @@ use grid_search_cardinal::{
         DistanceMap, PopulateContext as DistanceMapPopulateContext,
         SearchContext as DistanceMapSearchContext,
     },
+    point_to_point::{expand, Context as PointToPointSearchContext},
     CanEnter,
 };
 use line_2d::LineSegment;
-use shadowcast::{vision_distance, VisionDistance};
+use rand::{seq::IteratorRandom, Rng};
+use serde::{Deserialize, Serialize};
+use shadowcast::VisionDistance;
 
 pub struct BehaviourContext {
     distance_map_to_player: DistanceMap,
+    // Lower values lead away from the player, towards cells with lots of space beyond them
+    distance_map_from_player: DistanceMap,
+    // False if there is nowhere far enough from the player to flee to, in which case the flee
+    // map isn't populated
+    can_flee_from_player: bool,
     distance_map_populate_context: DistanceMapPopulateContext,
     distance_map_search_context: DistanceMapSearchContext,
+    point_to_point_search_context: PointToPointSearchContext,
+}
+
+// An empty context, only used as a placeholder when loading a saved game
+impl Default for BehaviourContext {
+    fn default() -> Self {
+        Self::new(Size::new(0, 0))
+    }
 }
 
 impl BehaviourContext {
     pub fn new(size: Size) -> Self {
         Self {
             distance_map_to_player: DistanceMap::new(size),
+            distance_map_from_player: DistanceMap::new(size),
+            can_flee_from_player: false,
             distance_map_populate_context: DistanceMapPopulateContext::default(),
             distance_map_search_context: DistanceMapSearchContext::new(size),
+            point_to_point_search_context: PointToPointSearchContext::new(size),
         }
     }
 
@@ -37,86 +57,574 @@ impl BehaviourContext {
             }
         }
         let player_coord = world.entity_coord(player).expect("player has no coord");
+        let can_enter = NpcCanEnterIgnoringOtherNpcs { world };
         const MAX_APPROACH_DISTANCE: u32 = 20;
         self.distance_map_populate_context.add(player_coord);
         self.distance_map_populate_context.populate_approach(
-            &NpcCanEnterIgnoringOtherNpcs { world },
+            &can_enter,
             MAX_APPROACH_DISTANCE,
             &mut self.distance_map_to_player,
         );
+        // The flee map is populated outwards from the player up to this distance, and then
+        // inwards from every cell reached at this distance. Fleeing npcs head for those cells,
+        // so they prefer escape routes that go somewhere over dead ends.
+        const FLEE_DISTANCE: u32 = 10;
+        self.distance_map_populate_context.add(player_coord);
+        self.distance_map_populate_context.populate_flee(
+            &can_enter,
+            FLEE_DISTANCE,
+            &mut self.distance_map_from_player,
+        );
+        // If no cell was far enough away, only the first step happens, which leaves the player's
+        // cell at distance 0
+        self.can_flee_from_player = self.distance_map_from_player.distance(player_coord) != Some(0);
     }
 }
 
 pub enum NpcAction {
     Wait,
     Move(CardinalDirection),
+    // Fire a projectile at the given character
+    Shoot(Entity),
+}
+
+// Every npc chases the player after seeing them, but what it does the rest of the time depends
+// on its archetype. Each npc type has a usual archetype, which can be overridden when an npc is
+// spawned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Archetype {
+    // Wanders around at random
+    Wanderer,
+    // Stays where it was spawned, and returns there after losing track of the player
+    Guard,
+    // Runs away from the player as soon as it is badly hurt
+    Coward,
+    // Stays still until the player comes close, so it isn't given away by approaching from afar
+    Ambusher,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+enum AgentState {
+    // Hasn't seen the player, or has given up looking for them
+    Idle,
+    // Heading for where the player was last seen, which is where the player is while the npc
+    // can still see them. An npc only knows where the player is while it can see them.
+    Hunting {
+        last_seen_player_coord: Coord,
+    },
+    // Looking around where the player was last seen, for a few more turns
+    Searching {
+        last_seen_player_coord: Coord,
+        turns_remaining: u32,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct Agent {
-    turns_since_last_saw_player: u32,
+    archetype: Archetype,
+    // Where the npc was spawned, which guards return to
+    post: Coord,
+    state: AgentState,
+}
+
+struct NpcCanEnter<'a> {
+    world: &'a World,
+    npc_type: NpcType,
 }
 
+impl<'a> CanEnter for NpcCanEnter<'a> {
+    fn can_enter(&self, coord: Coord) -> bool {
+        self.world.can_npc_enter(coord, self.npc_type)
+    }
+}
+
+// Uses the same vision distance and model of opacity as the player's shadowcast vision: the
+// opacity of each cell between the npc and its target is subtracted from the npc's visibility,
+// and the target is out of sight if nothing is left before reaching it
 fn npc_has_line_of_sight(src: Coord, dst: Coord, world: &World) -> bool {
-    const NPC_VISION_DISTANCE_SQUARED: u32 = 100;
-    const NPC_VISION_DISTANCE: vision_distance::Circle =
-        vision_distance::Circle::new_squared(NPC_VISION_DISTANCE_SQUARED);
     if src == dst {
         return true;
     }
-    for coord in LineSegment::new(src, dst).iter() {
+    let mut visibility: u8 = 255;
+    for coord in LineSegment::new(src, dst).iter().skip(1) {
         let src_to_coord = coord - src;
-        if !NPC_VISION_DISTANCE.in_range(src_to_coord) {
+        if !VISION_DISTANCE.in_range(src_to_coord) {
             return false;
         }
-        if !world.can_npc_see_through_cell(coord) {
+        if visibility == 0 {
             return false;
         }
+        visibility = visibility.saturating_sub(world.opacity_at(coord));
     }
     true
 }
 
 impl Agent {
-    pub fn new() -> Self {
+    pub fn new(archetype: Archetype, post: Coord) -> Self {
         Self {
-            turns_since_last_saw_player: u32::MAX,
+            archetype,
+            post,
+            state: AgentState::Idle,
         }
     }
 
-    pub fn act(
+    // Wounded npcs run away from the player, and cowards run away much sooner than others
+    fn wants_to_flee(&self, entity: Entity, world: &World) -> bool {
+        let flee_at_percent = match self.archetype {
+            Archetype::Coward => 50,
+            _ => 25,
+        };
+        world
+            .hit_points(entity)
+            .is_some_and(|hit_points| hit_points.current * 100 <= hit_points.max * flee_at_percent)
+    }
+
+    pub fn act<R: Rng>(
         &mut self,
         entity: Entity,
         player: Entity,
         world: &World,
         behaviour_context: &mut BehaviourContext,
+        rng: &mut R,
     ) -> NpcAction {
-        struct NpcCanEnter<'a> {
-            world: &'a World,
+        let npc_type = world.npc_type(entity).expect("npc has no npc type");
+        let npc_coord = world.entity_coord(entity).expect("npc has no coord");
+        let player_coord = world.entity_coord(player).expect("player has no coord");
+        let can_enter = NpcCanEnter { world, npc_type };
+        let sees_player = npc_has_line_of_sight(npc_coord, player_coord, world);
+        const AMBUSH_DISTANCE_SQUARED: u32 = 9;
+        let notices_player = sees_player
+            && (self.archetype != Archetype::Ambusher
+                || self.state != AgentState::Idle
+                || npc_coord.distance2(player_coord) <= AMBUSH_DISTANCE_SQUARED);
+        if notices_player {
+            self.state = AgentState::Hunting {
+                last_seen_player_coord: player_coord,
+            };
         }
-        impl<'a> CanEnter for NpcCanEnter<'a> {
-            fn can_enter(&self, coord: Coord) -> bool {
-                self.world.can_npc_enter(coord)
+        if self.wants_to_flee(entity, world) {
+            if !sees_player {
+                // Stays hidden rather than going looking for the player
+                self.state = AgentState::Idle;
+                return NpcAction::Wait;
+            }
+            if let Some(direction) =
+                flee_direction(npc_coord, player_coord, &can_enter, behaviour_context)
+            {
+                return NpcAction::Move(direction);
+            }
+            // Cornered, so it fights, but it doesn't leave its corner to do so
+            if npc_coord.distance2(player_coord) > 1 {
+                return NpcAction::Wait;
             }
         }
-        let npc_coord = world.entity_coord(entity).expect("npc has no coord");
-        let player_coord = world.entity_coord(player).expect("player has no coord");
-        if npc_has_line_of_sight(npc_coord, player_coord, world) {
-            self.turns_since_last_saw_player = 0;
-        } else {
-            self.turns_since_last_saw_player = self.turns_since_last_saw_player.saturating_add(1);
+        if notices_player && npc_type.has_ranged_attack() {
+            // Npcs that shoot try to keep their distance, backing away if the player comes too
+            // close
+            const PREFERRED_DISTANCE_SQUARED: u32 = 16;
+            if npc_coord.distance2(player_coord) < PREFERRED_DISTANCE_SQUARED {
+                if let Some(direction) =
+                    flee_direction(npc_coord, player_coord, &can_enter, behaviour_context)
+                {
+                    return NpcAction::Move(direction);
+                }
+            }
+            // The npc can see the player, but something else might be in the way of a shot
+            if world.is_projectile_path_clear(npc_coord, player_coord) {
+                return NpcAction::Shoot(player);
+            }
+        }
+        if notices_player {
+            // The distance map leads to the player's true position, which the npc knows while it
+            // can see them
+            const SEARCH_DISTANCE: u32 = 5;
+            return match behaviour_context.distance_map_search_context.search_first(
+                &can_enter,
+                npc_coord,
+                SEARCH_DISTANCE,
+                &behaviour_context.distance_map_to_player,
+            ) {
+                None => NpcAction::Wait,
+                Some(direction) => NpcAction::Move(direction),
+            };
+        }
+        if let AgentState::Hunting {
+            last_seen_player_coord,
+        } = self.state
+        {
+            let direction = behaviour_context
+                .point_to_point_search_context
+                .point_to_point_search_first(
+                    expand::Sequential,
+                    &can_enter,
+                    npc_coord,
+                    last_seen_player_coord,
+                );
+            if let Ok(Some(direction)) = direction {
+                return NpcAction::Move(direction);
+            }
+            // Either the npc has arrived, or it can't get there
+            const NUM_TURNS_TO_SEARCH: u32 = 5;
+            self.state = AgentState::Searching {
+                last_seen_player_coord,
+                turns_remaining: NUM_TURNS_TO_SEARCH,
+            };
         }
-        const MAX_TURNS_TO_CHASE_PLAYER_AFTER_LOSING_SIGHT: u32 = 3;
-        if self.turns_since_last_saw_player > MAX_TURNS_TO_CHASE_PLAYER_AFTER_LOSING_SIGHT {
-            return NpcAction::Wait;
+        if let AgentState::Searching {
+            last_seen_player_coord,
+            ref mut turns_remaining,
+        } = self.state
+        {
+            if *turns_remaining > 0 {
+                *turns_remaining -= 1;
+                const SEARCH_RADIUS_SQUARED: u32 = 9;
+                return CardinalDirection::all()
+                    .filter(|direction| {
+                        let coord = npc_coord + direction.coord();
+                        can_enter.can_enter(coord)
+                            && coord.distance2(last_seen_player_coord) <= SEARCH_RADIUS_SQUARED
+                    })
+                    .choose(rng)
+                    .map(NpcAction::Move)
+                    .unwrap_or(NpcAction::Wait);
+            }
+            self.state = AgentState::Idle;
+        }
+        match self.archetype {
+            Archetype::Wanderer => {
+                // Wandering npcs stop to look around half of the time
+                if rng.gen() {
+                    return NpcAction::Wait;
+                }
+                CardinalDirection::all()
+                    .filter(|direction| can_enter.can_enter(npc_coord + direction.coord()))
+                    .choose(rng)
+                    .map(NpcAction::Move)
+                    .unwrap_or(NpcAction::Wait)
+            }
+            Archetype::Guard => match behaviour_context
+                .point_to_point_search_context
+                .point_to_point_search_first(expand::Sequential, &can_enter, npc_coord, self.post)
+            {
+                Ok(Some(direction)) => NpcAction::Move(direction),
+                _ => NpcAction::Wait,
+            },
+            Archetype::Coward | Archetype::Ambusher => NpcAction::Wait,
         }
+    }
+}
+
+// Returns the direction an npc fleeing from the player should move in, or None if it is cornered
+fn flee_direction(
+    npc_coord: Coord,
+    player_coord: Coord,
+    can_enter: &NpcCanEnter,
+    behaviour_context: &mut BehaviourContext,
+) -> Option<CardinalDirection> {
+    if behaviour_context.can_flee_from_player {
         const SEARCH_DISTANCE: u32 = 5;
-        match behaviour_context.distance_map_search_context.search_first(
-            &NpcCanEnter { world },
+        if let Some(direction) = behaviour_context.distance_map_search_context.search_first(
+            can_enter,
             npc_coord,
             SEARCH_DISTANCE,
-            &behaviour_context.distance_map_to_player,
+            &behaviour_context.distance_map_from_player,
         ) {
-            None => NpcAction::Wait,
-            Some(direction) => NpcAction::Move(direction),
+            return Some(direction);
         }
     }
+    // Either there is nowhere far enough away to flee to, or the npc is already at (or beyond)
+    // the edge of the flee map, so it just keeps its distance
+    direction_away_from(npc_coord, player_coord, can_enter)
+}
+
+// Returns the direction of the neighbouring cell furthest from the player, if moving there would
+// take the npc further away
+fn direction_away_from(
+    npc_coord: Coord,
+    player_coord: Coord,
+    can_enter: &NpcCanEnter,
+) -> Option<CardinalDirection> {
+    CardinalDirection::all()
+        .map(|direction| (direction, npc_coord + direction.coord()))
+        .filter(|&(_, coord)| can_enter.can_enter(coord))
+        .map(|(direction, coord)| (direction, coord.distance2(player_coord)))
+        .filter(|&(_, distance2)| distance2 > npc_coord.distance2(player_coord))
+        .max_by_key(|&(_, distance2)| distance2)
+        .map(|(direction, _)| direction)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::visibility::{CellVisibility, VisibilityAlgorithm, VisibilityGrid};
+    use crate::world::{HitPoints, Light};
+    use rand::SeedableRng;
+    use rand_isaac::Isaac64Rng;
+    use rgb24::Rgb24;
+
+    // Returns whether the player can see the orc and whether the orc can see the player, in a
+    // single row of cells. The orc carries a light so that whether the player can see it only
+    // depends on what is in the way.
+    fn sight_both_ways(row: &str) -> (bool, bool) {
+        let (mut world, populate) = World::from_rows(&[row]);
+        let player = populate.player_entity;
+        for npc in populate.ai_state.entities() {
+            let light = Light {
+                colour: Rgb24::new_grey(255),
+                radius: 1,
+            };
+            world.components.light.insert(npc, light);
+        }
+        let player_coord = world.entity_coord(player).unwrap();
+        let npc_coord = Coord::new(row.find('o').unwrap() as i32, 0);
+        let mut visibility_grid = VisibilityGrid::new(world.size());
+        visibility_grid.update(
+            player_coord,
+            &world,
+            &mut shadowcast::Context::default(),
+            VisibilityAlgorithm::Shadowcast,
+        );
+        let player_sees_npc =
+            visibility_grid.cell_visibility(npc_coord) == CellVisibility::Currently;
+        let npc_sees_player = npc_has_line_of_sight(npc_coord, player_coord, &world);
+        (player_sees_npc, npc_sees_player)
+    }
+
+    #[test]
+    fn npcs_and_the_player_see_through_the_same_cells() {
+        let cases = [
+            ("@...o", true),
+            ("@\"\".o", true),
+            ("@\"\"\".o", false),
+            ("@~~.o", true),
+            ("@~~~o", false),
+            ("@~\".o", true),
+            ("@~\"\"o", false),
+            ("@=.o", true),
+            ("@#.o", false),
+            ("@..............o", true),
+            ("@....................o", false),
+        ];
+        for &(row, visible) in cases.iter() {
+            assert_eq!(sight_both_ways(row), (visible, visible), "{}", row);
+        }
+    }
+
+    // A map containing the player and one npc, see `terrain::parse_level` for the format
+    struct Scenario {
+        world: World,
+        player: Entity,
+        npc: Entity,
+        agent: Agent,
+        behaviour_context: BehaviourContext,
+        rng: Isaac64Rng,
+    }
+
+    impl Scenario {
+        fn new(row: &str) -> Self {
+            Self::from_rows(&[row])
+        }
+
+        fn from_rows(rows: &[&str]) -> Self {
+            let (world, mut populate) = World::from_rows(rows);
+            let size = world.size();
+            let npc = populate.ai_state.entities().next().unwrap();
+            let agent = populate.ai_state.remove(npc).unwrap();
+            Self {
+                world,
+                player: populate.player_entity,
+                npc,
+                agent,
+                behaviour_context: BehaviourContext::new(size),
+                rng: Isaac64Rng::seed_from_u64(0),
+            }
+        }
+
+        fn act(&mut self) -> NpcAction {
+            self.behaviour_context.update(self.player, &self.world);
+            self.agent.act(
+                self.npc,
+                self.player,
+                &self.world,
+                &mut self.behaviour_context,
+                &mut self.rng,
+            )
+        }
+
+        // Lets the npc take one action, returning its position afterwards. Only moves are
+        // carried out.
+        fn step(&mut self) -> i32 {
+            if let NpcAction::Move(direction) = self.act() {
+                self.world.maybe_move_character(
+                    self.npc,
+                    direction,
+                    &mut Vec::new(),
+                    &mut self.rng,
+                );
+            }
+            self.npc_coord().x
+        }
+
+        fn npc_coord(&self) -> Coord {
+            self.world.entity_coord(self.npc).unwrap()
+        }
+
+        fn set_npc_hit_points(&mut self, current: u32) {
+            let hit_points = HitPoints { current, max: 4 };
+            self.world
+                .components
+                .hit_points
+                .insert(self.npc, hit_points);
+        }
+
+        fn move_player_to(&mut self, x: i32) {
+            self.world
+                .spatial_table
+                .update_coord(self.player, Coord::new(x, 0))
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn wanderers_move_without_seeing_the_player() {
+        let mut scenario = Scenario::new("@#......o...");
+        let xs = (0..20).map(|_| scenario.step()).collect::<Vec<_>>();
+        assert!(xs.iter().any(|&x| x != 8));
+    }
+
+    #[test]
+    fn guards_return_to_their_post() {
+        let mut scenario = Scenario::new("@#.....g");
+        scenario
+            .world
+            .spatial_table
+            .update_coord(scenario.npc, Coord::new(3, 0))
+            .unwrap();
+        let xs = (0..6).map(|_| scenario.step()).collect::<Vec<_>>();
+        assert_eq!(xs, [4, 5, 6, 7, 7, 7]);
+    }
+
+    #[test]
+    fn npcs_search_where_they_last_saw_the_player() {
+        let mut scenario = Scenario::new("...#.@....g");
+        assert_eq!(scenario.step(), 9);
+        // The player disappears behind the wall, where the guard can't follow. It goes to where
+        // it last saw the player rather than to where the player really is.
+        scenario.move_player_to(0);
+        let xs = (0..4).map(|_| scenario.step()).collect::<Vec<_>>();
+        assert_eq!(xs, [8, 7, 6, 5]);
+        // It looks around nearby for a few turns, then gives up and returns to its post
+        for _ in 0..5 {
+            let x = scenario.step();
+            assert!((4..=8).contains(&x), "{}", x);
+        }
+        let xs = (0..10).map(|_| scenario.step()).collect::<Vec<_>>();
+        assert_eq!(xs.last(), Some(&10));
+    }
+
+    #[test]
+    fn ambushers_wait_until_the_player_is_close() {
+        let mut scenario = Scenario::new("@......a");
+        assert_eq!(scenario.step(), 7);
+        assert_eq!(scenario.step(), 7);
+        scenario.move_player_to(4);
+        assert_eq!(scenario.step(), 6);
+        // Once it has been seen, it keeps chasing the player
+        scenario.move_player_to(0);
+        assert_eq!(scenario.step(), 5);
+    }
+
+    #[test]
+    fn cowards_flee_when_badly_hurt() {
+        let mut scenario = Scenario::new("...@..c...");
+        assert_eq!(scenario.step(), 5);
+        scenario.set_npc_hit_points(2);
+        assert_eq!(scenario.step(), 6);
+        assert_eq!(scenario.step(), 7);
+    }
+
+    #[test]
+    fn cornered_cowards_fight() {
+        let mut scenario = Scenario::new("@c");
+        scenario.set_npc_hit_points(1);
+        scenario.step();
+        let player_hit_points = scenario.world.hit_points(scenario.player).unwrap();
+        assert!(player_hit_points.current < player_hit_points.max);
+    }
+
+    #[test]
+    fn cornered_cowards_wait_for_the_player_to_come_to_them() {
+        let mut scenario = Scenario::new("@....c");
+        scenario.set_npc_hit_points(1);
+        assert_eq!(scenario.step(), 5);
+        assert_eq!(scenario.step(), 5);
+    }
+
+    #[test]
+    fn fleeing_npcs_keep_going_past_the_flee_distance() {
+        let mut scenario = Scenario::new("@.........c........");
+        scenario.set_npc_hit_points(1);
+        // It's at the edge of the flee map, but it can still get further away, until it's
+        // cornered at the end of the row
+        let xs = (0..10).map(|_| scenario.step()).collect::<Vec<_>>();
+        assert_eq!(xs, [11, 12, 13, 14, 15, 16, 17, 18, 18, 18]);
+    }
+
+    #[test]
+    fn only_badly_wounded_npcs_flee_unless_they_are_cowards() {
+        let mut scenario = Scenario::new(".#.@..o...");
+        scenario.set_npc_hit_points(2);
+        assert_eq!(scenario.step(), 5);
+        scenario.set_npc_hit_points(1);
+        assert_eq!(scenario.step(), 6);
+        // Once out of sight, it hides instead of looking for the player
+        scenario.move_player_to(0);
+        assert_eq!(scenario.step(), 6);
+    }
+
+    #[test]
+    fn fleeing_npcs_avoid_dead_ends() {
+        // Moving east would take the coward furthest from the player, but it would be trapped
+        // at the end of the corridor. Going north leads to open space.
+        let mut scenario = Scenario::from_rows(&[
+            "##.........",
+            "######.####",
+            "######.####",
+            "@.....c...#",
+            "###########",
+        ]);
+        scenario.set_npc_hit_points(1);
+        scenario.step();
+        assert_eq!(scenario.npc_coord(), Coord::new(6, 2));
+    }
+
+    #[test]
+    fn archers_shoot_from_a_distance() {
+        let mut scenario = Scenario::new("@......A");
+        assert!(matches!(scenario.act(), NpcAction::Shoot(target) if target == scenario.player));
+    }
+
+    #[test]
+    fn archers_back_away_from_the_player() {
+        let mut scenario = Scenario::new("@..A......");
+        assert_eq!(scenario.step(), 4);
+        // Once far enough away, it shoots instead
+        assert!(matches!(scenario.act(), NpcAction::Shoot(_)));
+    }
+
+    #[test]
+    fn archers_back_away_from_the_player_when_far_away_by_path() {
+        // The archer is close to the player, but it's a long way round the glass
+        let mut scenario = Scenario::from_rows(&[
+            "@=A....", ".=.....", ".=.....", ".=.....", ".=.....", ".=.....", ".......",
+        ]);
+        assert_eq!(scenario.step(), 3);
+    }
+
+    #[test]
+    fn archers_only_shoot_when_nothing_is_in_the_way() {
+        // The archer can see the player through the glass, but can't shoot through it
+        let mut scenario = Scenario::new("@.=...A");
+        assert!(!matches!(scenario.act(), NpcAction::Shoot(_)));
+    }
 }
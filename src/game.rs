@@ -1,49 +1,114 @@
 use crate::behaviour::{Agent, BehaviourContext, NpcAction};
+use crate::terrain::{GeneratorChoice, SpawnTable};
 use crate::visibility::{CellVisibility, VisibilityAlgorithm, VisibilityGrid};
-use crate::world::{HitPoints, Location, NpcType, Populate, Tile, World};
-use coord_2d::Size;
+use crate::world::{
+    Action, Experience, HitPoints, ItemType, LevelUpChoice, Location, NpcType, Populate, Tile,
+    World,
+};
+use coord_2d::{Coord, Size};
 use direction::CardinalDirection;
 use entity_table::ComponentTable;
 use entity_table::Entity;
-use rand::SeedableRng;
+use rand::{Rng, SeedableRng};
 use rand_isaac::Isaac64Rng;
+use rgb24::Rgb24;
+use serde::{Deserialize, Serialize};
 
 pub struct EntityToRender {
     pub tile: Tile,
     pub location: Location,
     pub visibility: CellVisibility,
+    pub light: Rgb24,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum LogMessage {
-    PlayerAttacksNpc(NpcType),
-    NpcAttacksPlayer(NpcType),
-    PlayerKillsNpc(NpcType),
-    NpcKillsPlayer(NpcType),
+    PlayerAttacksNpc(NpcType, u32),
+    NpcAttacksPlayer(NpcType, u32),
+    PlayerKillsNpc(NpcType, u32),
+    NpcKillsPlayer(NpcType, u32),
+    PlayerGets(ItemType),
+    PlayerInventoryIsFull,
+    NoItemUnderPlayer,
+    PlayerHeals(u32),
+    PlayerIsAtFullHealth,
+    PlayerDrops(ItemType),
+    NoSpaceToDropItem,
+    PlayerDescends(u32),
+    NoStairsUnderPlayer,
+    PlayerLevelsUp(u32),
+    PlayerOpensDoor,
+    NpcShootsPlayer(NpcType, u32),
 }
 
+// The path of a projectile, from the cell it was fired from to the cell it hit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Projectile {
+    pub src: Coord,
+    pub dst: Coord,
+}
+
+// An action taken by the player. Every change the player makes to the game state goes through
+// `GameState::perform_action`, so a game can be reproduced from its seed and a list of actions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameAction {
+    Move(CardinalDirection),
+    Wait,
+    GetItem,
+    UseItem(usize),
+    DropItem(usize),
+    Descend,
+    LevelUp(LevelUpChoice),
+}
+
+// Level 1 is generated from the base seed itself. Each deeper level takes its seed from a
+// generator seeded with the base seed, so every level is distinct but reproducible.
+fn level_rng_seed(rng_seed: u64, dungeon_level: u32) -> u64 {
+    let mut seed_rng = Isaac64Rng::seed_from_u64(rng_seed);
+    (1..dungeon_level).fold(rng_seed, |_, _| seed_rng.gen())
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct GameState {
     world: World,
     player_entity: Entity,
+    #[serde(skip)]
     shadowcast_context: shadowcast::Context<u8>,
     visibility_grid: VisibilityGrid,
     ai_state: ComponentTable<Agent>,
+    #[serde(skip)]
     behaviour_context: BehaviourContext,
     message_log: Vec<LogMessage>,
+    // Projectiles fired since the player's last action, which are shown until the next one
+    #[serde(skip)]
+    projectiles: Vec<Projectile>,
+    // All randomness during play (e.g. combat rolls) must come from this generator, so that a
+    // game is entirely determined by its seed and the sequence of player actions. It is seeded
+    // with the base seed and first used to generate level 1.
+    rng: Isaac64Rng,
+    rng_seed: u64,
+    generator_choice: GeneratorChoice,
+    dungeon_level: u32,
 }
 
 impl GameState {
     pub fn new(
         screen_size: Size,
         rng_seed: u64,
+        generator_choice: GeneratorChoice,
         initial_visibility_algorithm: VisibilityAlgorithm,
     ) -> Self {
+        let dungeon_level = 1;
         let mut world = World::new(screen_size);
-        let mut rng = Isaac64Rng::seed_from_u64(rng_seed);
+        let mut rng = Isaac64Rng::seed_from_u64(level_rng_seed(rng_seed, dungeon_level));
         let Populate {
             player_entity,
             ai_state,
-        } = world.populate(&mut rng);
+        } = world.populate(
+            generator_choice.for_dungeon_level(dungeon_level),
+            SpawnTable::for_dungeon_level(dungeon_level),
+            &mut rng,
+        );
         let shadowcast_context = shadowcast::Context::default();
         let visibility_grid = VisibilityGrid::new(screen_size);
         let behaviour_context = BehaviourContext::new(screen_size);
@@ -55,34 +120,155 @@ impl GameState {
             ai_state,
             behaviour_context,
             message_log: Vec::new(),
+            projectiles: Vec::new(),
+            rng,
+            rng_seed,
+            generator_choice,
+            dungeon_level,
         };
         game_state.update_visibility(initial_visibility_algorithm);
         game_state
     }
 
+    // The shadowcast and behaviour contexts are only scratch space, so they aren't saved. This
+    // recreates them after loading a saved game.
+    pub fn rebuild_unsaved_state(&mut self) {
+        self.shadowcast_context = shadowcast::Context::default();
+        self.behaviour_context = BehaviourContext::new(self.world.size());
+    }
+
+    pub fn maybe_player_descend(&mut self) {
+        let player_coord = self
+            .world
+            .spatial_table
+            .coord_of(self.player_entity)
+            .unwrap();
+        if !self.world.is_stairs_at(player_coord) {
+            self.message_log.push(LogMessage::NoStairsUnderPlayer);
+            return;
+        }
+        let player_data = self.world.clone_player_data(self.player_entity);
+        let size = self.world.size();
+        self.dungeon_level += 1;
+        let mut world = World::new(size);
+        // Deeper levels are generated with their own generator so that the layout of a level
+        // doesn't depend on how the previous levels were played
+        let mut level_rng =
+            Isaac64Rng::seed_from_u64(level_rng_seed(self.rng_seed, self.dungeon_level));
+        let Populate {
+            player_entity,
+            ai_state,
+        } = world.populate(
+            self.generator_choice.for_dungeon_level(self.dungeon_level),
+            SpawnTable::for_dungeon_level(self.dungeon_level),
+            &mut level_rng,
+        );
+        world.replace_player_data(player_entity, player_data);
+        self.world = world;
+        self.player_entity = player_entity;
+        self.ai_state = ai_state;
+        self.visibility_grid = VisibilityGrid::new(size);
+        self.message_log
+            .push(LogMessage::PlayerDescends(self.dungeon_level));
+    }
+
+    pub fn perform_action(&mut self, action: GameAction) {
+        self.projectiles.clear();
+        match action {
+            GameAction::Move(direction) => self.maybe_move_player(direction),
+            GameAction::Wait => self.wait_player(),
+            GameAction::GetItem => self.maybe_player_get_item(),
+            GameAction::UseItem(inventory_index) => self.maybe_player_use_item(inventory_index),
+            GameAction::DropItem(inventory_index) => self.maybe_player_drop_item(inventory_index),
+            GameAction::Descend => self.maybe_player_descend(),
+            GameAction::LevelUp(choice) => self.player_level_up(choice),
+        }
+    }
+
     pub fn wait_player(&mut self) {
-        self.ai_turn();
+        self.pass_time(Action::Wait);
     }
 
     pub fn maybe_move_player(&mut self, direction: CardinalDirection) {
-        self.world
-            .maybe_move_character(self.player_entity, direction, &mut self.message_log);
-        self.ai_turn();
+        let action = self.world.maybe_move_character(
+            self.player_entity,
+            direction,
+            &mut self.message_log,
+            &mut self.rng,
+        );
+        self.pass_time(action);
+    }
+
+    pub fn maybe_player_get_item(&mut self) {
+        if self
+            .world
+            .maybe_get_item(self.player_entity, &mut self.message_log)
+            .is_ok()
+        {
+            self.pass_time(Action::GetItem);
+        }
+    }
+
+    pub fn maybe_player_use_item(&mut self, inventory_index: usize) {
+        if self
+            .world
+            .maybe_use_item(self.player_entity, inventory_index, &mut self.message_log)
+            .is_ok()
+        {
+            self.pass_time(Action::UseItem);
+        }
+    }
+
+    pub fn maybe_player_drop_item(&mut self, inventory_index: usize) {
+        if self
+            .world
+            .maybe_drop_item(self.player_entity, inventory_index, &mut self.message_log)
+            .is_ok()
+        {
+            self.pass_time(Action::DropItem);
+        }
+    }
+
+    pub fn has_pending_level_up(&self) -> bool {
+        self.world.has_unspent_level_up(self.player_entity)
+    }
+
+    pub fn player_level_up(&mut self, choice: LevelUpChoice) {
+        self.world.level_up(self.player_entity, choice);
     }
 
     pub fn entities_to_render<'a>(&'a self) -> impl 'a + Iterator<Item = EntityToRender> {
         let tile_component = &self.world.components.tile;
         let spatial_table = &self.world.spatial_table;
         let visibility_grid = &self.visibility_grid;
-        tile_component.iter().filter_map(move |(entity, &tile)| {
+        let currently_visible = tile_component.iter().filter_map(move |(entity, &tile)| {
             let &location = spatial_table.location_of(entity)?;
             let visibility = visibility_grid.cell_visibility(location.coord);
+            if visibility != CellVisibility::Currently {
+                return None;
+            }
+            let light = visibility_grid.cell_light(location.coord);
             Some(EntityToRender {
                 tile,
                 location,
                 visibility,
+                light,
             })
-        })
+        });
+        // Cells which aren't currently visible are drawn as the player remembers them, so
+        // changes that the player hasn't seen aren't revealed
+        let remembered = visibility_grid
+            .remembered_tiles()
+            .map(|(coord, layer, tile)| EntityToRender {
+                tile,
+                location: Location {
+                    coord,
+                    layer: Some(layer),
+                },
+                visibility: CellVisibility::Previously,
+                light: Rgb24::new_grey(0),
+            });
+        currently_visible.chain(remembered)
     }
 
     pub fn update_visibility(&mut self, visibility_algorithm: VisibilityAlgorithm) {
@@ -99,7 +285,10 @@ impl GameState {
         );
     }
 
-    fn ai_turn(&mut self) {
+    // Charges the player for the action they just took, then lets the npcs act until it is the
+    // player's turn again
+    fn pass_time(&mut self, player_action: Action) {
+        self.world.spend_energy(self.player_entity, player_action);
         self.behaviour_context
             .update(self.player_entity, &self.world);
         let dead_entities = self
@@ -110,23 +299,74 @@ impl GameState {
         for dead_entity in dead_entities {
             self.ai_state.remove(dead_entity);
         }
-        for (entity, agent) in self.ai_state.iter_mut() {
-            let npc_action = agent.act(
-                entity,
-                self.player_entity,
-                &self.world,
-                &mut self.behaviour_context,
-            );
-            match npc_action {
-                NpcAction::Wait => (),
-                NpcAction::Move(direction) => {
-                    self.world
-                        .maybe_move_character(entity, direction, &mut self.message_log)
-                },
+        loop {
+            while let Some(entity) = self.next_npc_to_act() {
+                self.npc_turn(entity);
+                if !self.is_player_alive() {
+                    return;
+                }
             }
+            // When the player and some npcs become ready on the same tick, the npcs go first
+            if self.world.is_ready_to_act(self.player_entity) {
+                return;
+            }
+            self.world.tick();
         }
     }
 
+    // Of the npcs that are ready to act, the one with the most energy goes first. Ties are
+    // broken by position, from top to bottom and then left to right, so the order doesn't
+    // depend on how entities happen to be stored.
+    fn next_npc_to_act(&self) -> Option<Entity> {
+        self.ai_state
+            .entities()
+            .filter(|&entity| {
+                self.world.is_living_character(entity) && self.world.is_ready_to_act(entity)
+            })
+            .filter_map(|entity| {
+                let energy = self.world.energy(entity)?;
+                let coord = self.world.entity_coord(entity)?;
+                Some((entity, energy, coord))
+            })
+            .max_by(|(_, energy_a, coord_a), (_, energy_b, coord_b)| {
+                energy_a
+                    .cmp(energy_b)
+                    .then_with(|| (coord_b.y, coord_b.x).cmp(&(coord_a.y, coord_a.x)))
+            })
+            .map(|(entity, _, _)| entity)
+    }
+
+    fn npc_turn(&mut self, entity: Entity) {
+        let agent = self.ai_state.get_mut(entity).expect("npc has no agent");
+        let npc_action = agent.act(
+            entity,
+            self.player_entity,
+            &self.world,
+            &mut self.behaviour_context,
+            &mut self.rng,
+        );
+        let action = match npc_action {
+            NpcAction::Wait => Action::Wait,
+            NpcAction::Move(direction) => self.world.maybe_move_character(
+                entity,
+                direction,
+                &mut self.message_log,
+                &mut self.rng,
+            ),
+            NpcAction::Shoot(target) => {
+                let src = self.world.entity_coord(entity).expect("npc has no coord");
+                let dst = self
+                    .world
+                    .entity_coord(target)
+                    .expect("target has no coord");
+                self.projectiles.push(Projectile { src, dst });
+                self.world
+                    .ranged_attack(entity, target, &mut self.message_log, &mut self.rng)
+            }
+        };
+        self.world.spend_energy(entity, action);
+    }
+
     pub fn is_player_alive(&self) -> bool {
         self.world.is_living_character(self.player_entity)
     }
@@ -137,7 +377,198 @@ impl GameState {
             .expect("player has no hit points")
     }
 
+    pub fn player_experience(&self) -> Experience {
+        self.world
+            .experience(self.player_entity)
+            .expect("player has no experience")
+    }
+
+    pub fn player_coord(&self) -> Coord {
+        self.world
+            .entity_coord(self.player_entity)
+            .expect("player has no coord")
+    }
+
+    // Every living NPC on the current level, with its coordinate and hit points
+    pub fn npcs<'a>(&'a self) -> impl 'a + Iterator<Item = (NpcType, Coord, HitPoints)> {
+        self.world
+            .components
+            .npc_type
+            .iter()
+            .filter(move |&(entity, _)| self.world.is_living_character(entity))
+            .filter_map(move |(entity, &npc_type)| {
+                let coord = self.world.entity_coord(entity)?;
+                let hit_points = self.world.hit_points(entity)?;
+                Some((npc_type, coord, hit_points))
+            })
+    }
+
+    pub fn size(&self) -> Size {
+        self.world.size()
+    }
+
+    pub fn cell_visibility(&self, coord: Coord) -> CellVisibility {
+        self.visibility_grid.cell_visibility(coord)
+    }
+
+    pub fn player_inventory_item_types<'a>(
+        &'a self,
+    ) -> impl 'a + Iterator<Item = Option<ItemType>> {
+        self.world
+            .inventory(self.player_entity)
+            .expect("player has no inventory")
+            .slots()
+            .iter()
+            .map(move |slot| slot.and_then(|item| self.world.item_type(item)))
+    }
+
+    pub fn dungeon_level(&self) -> u32 {
+        self.dungeon_level
+    }
+
     pub fn message_log(&self) -> &[LogMessage] {
         &self.message_log
     }
+
+    pub fn projectiles(&self) -> &[Projectile] {
+        &self.projectiles
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::world::Speed;
+
+    // Plays a game with a fixed sequence of pseudo-random player actions and returns the
+    // serialized state of the game at the end
+    fn play(rng_seed: u64) -> Vec<u8> {
+        let mut game_state = GameState::new(
+            Size::new(40, 25),
+            rng_seed,
+            GeneratorChoice::default(),
+            VisibilityAlgorithm::Shadowcast,
+        );
+        let mut input_rng = Isaac64Rng::seed_from_u64(0);
+        for _ in 0..500 {
+            if !game_state.is_player_alive() {
+                break;
+            }
+            match input_rng.gen_range(0..8) {
+                0 => game_state.maybe_move_player(CardinalDirection::North),
+                1 => game_state.maybe_move_player(CardinalDirection::East),
+                2 => game_state.maybe_move_player(CardinalDirection::South),
+                3 => game_state.maybe_move_player(CardinalDirection::West),
+                4 => game_state.wait_player(),
+                5 => game_state.maybe_player_get_item(),
+                6 => game_state.maybe_player_use_item(0),
+                _ => game_state.maybe_player_descend(),
+            }
+            game_state.update_visibility(VisibilityAlgorithm::Shadowcast);
+        }
+        bincode::serialize(&game_state).unwrap()
+    }
+
+    #[test]
+    fn same_seed_and_input_give_same_game() {
+        for rng_seed in 0..20 {
+            assert_eq!(play(rng_seed), play(rng_seed));
+        }
+    }
+
+    #[test]
+    fn different_seeds_give_different_games() {
+        assert_ne!(play(0), play(1));
+    }
+
+    // A game on a single row of cells, see `terrain::parse_level` for the format
+    fn game_state_from_row(row: &str) -> GameState {
+        let (
+            world,
+            Populate {
+                player_entity,
+                ai_state,
+            },
+        ) = World::from_rows(&[row]);
+        let size = world.size();
+        GameState {
+            world,
+            player_entity,
+            shadowcast_context: shadowcast::Context::default(),
+            visibility_grid: VisibilityGrid::new(size),
+            ai_state,
+            behaviour_context: BehaviourContext::new(size),
+            message_log: Vec::new(),
+            projectiles: Vec::new(),
+            rng: Isaac64Rng::seed_from_u64(0),
+            rng_seed: 0,
+            generator_choice: GeneratorChoice::default(),
+            dungeon_level: 1,
+        }
+    }
+
+    fn npc_x(game_state: &GameState) -> i32 {
+        game_state.npcs().next().unwrap().1.x
+    }
+
+    #[test]
+    fn fast_npcs_act_twice_per_turn() {
+        let mut game_state = game_state_from_row("@.......o");
+        let npc = game_state.ai_state.entities().next().unwrap();
+        game_state.world.components.speed.insert(npc, Speed(20));
+        game_state.wait_player();
+        assert_eq!(npc_x(&game_state), 6);
+        game_state.wait_player();
+        assert_eq!(npc_x(&game_state), 4);
+    }
+
+    #[test]
+    fn slow_npcs_skip_turns() {
+        // Trolls have 7/10 of the player's speed, so they skip every third or fourth turn
+        let mut game_state = game_state_from_row("@........T");
+        let mut xs = Vec::new();
+        for _ in 0..6 {
+            game_state.wait_player();
+            xs.push(npc_x(&game_state));
+        }
+        assert_eq!(xs, [9, 8, 7, 7, 6, 5]);
+    }
+
+    #[test]
+    fn attacking_takes_longer_than_moving() {
+        // The player attacks the orc next to them while another orc approaches. Attacks cost
+        // more energy than moves, so in the time the player takes to attack five times the
+        // other orc moves six times.
+        let mut game_state = game_state_from_row("@o.......o");
+        let near_npc = game_state.ai_state.entities().next().unwrap();
+        game_state.world.components.hit_points.insert(
+            near_npc,
+            HitPoints {
+                current: 1000,
+                max: 1000,
+            },
+        );
+        for _ in 0..5 {
+            game_state.maybe_move_player(CardinalDirection::East);
+        }
+        let far_npc_x = game_state.npcs().map(|(_, coord, _)| coord.x).max();
+        assert_eq!(far_npc_x, Some(3));
+    }
+
+    #[test]
+    fn shots_are_logged_and_shown_until_the_next_action() {
+        let mut game_state = game_state_from_row("@.....A");
+        game_state.perform_action(GameAction::Wait);
+        assert!(matches!(
+            game_state.message_log(),
+            [LogMessage::NpcShootsPlayer(NpcType::Archer, _)]
+        ));
+        let projectile = Projectile {
+            src: Coord::new(6, 0),
+            dst: Coord::new(0, 0),
+        };
+        assert_eq!(game_state.projectiles(), [projectile]);
+        game_state.perform_action(GameAction::LevelUp(LevelUpChoice::Attack));
+        assert!(game_state.projectiles().is_empty());
+    }
 }
@@ -0,0 +1,98 @@
+use crate::game::{GameAction, GameState, LogMessage};
+use crate::terrain::GeneratorChoice;
+use crate::visibility::{CellVisibility, VisibilityAlgorithm};
+use crate::world::{HitPoints, NpcType};
+use coord_2d::{Coord, Size};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NpcObservation {
+    pub npc_type: NpcType,
+    pub coord: Coord,
+    pub hit_points: HitPoints,
+}
+
+// The state of a game at one point in time, as plain data that tests can inspect and compare
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Observation {
+    pub is_player_alive: bool,
+    pub player_coord: Coord,
+    pub player_hit_points: HitPoints,
+    pub dungeon_level: u32,
+    // Every living NPC on the level, whether or not the player can see it
+    pub npcs: Vec<NpcObservation>,
+    // The messages logged by the most recent action
+    pub messages: Vec<LogMessage>,
+    // The cells the player can currently see
+    pub visible_cells: Vec<Coord>,
+}
+
+// Runs a game without a frontend. Actions are applied to the `GameState` in the same way as
+// `App` applies them, so a simulation plays out exactly like the same inputs in a window.
+pub struct Simulation {
+    game_state: GameState,
+    visibility_algorithm: VisibilityAlgorithm,
+}
+
+impl Simulation {
+    pub fn new(
+        size: Size,
+        rng_seed: u64,
+        generator_choice: GeneratorChoice,
+        visibility_algorithm: VisibilityAlgorithm,
+    ) -> Self {
+        Self {
+            game_state: GameState::new(size, rng_seed, generator_choice, visibility_algorithm),
+            visibility_algorithm,
+        }
+    }
+
+    pub fn game_state(&self) -> &GameState {
+        &self.game_state
+    }
+
+    // Once the player is dead the game no longer accepts input, so further actions are ignored
+    pub fn step(&mut self, action: GameAction) -> Observation {
+        let num_messages_before = self.game_state.message_log().len();
+        if self.game_state.is_player_alive() {
+            self.game_state.perform_action(action);
+            self.game_state.update_visibility(self.visibility_algorithm);
+        }
+        self.observe_since(num_messages_before)
+    }
+
+    pub fn run<I: IntoIterator<Item = GameAction>>(&mut self, actions: I) -> Vec<Observation> {
+        actions
+            .into_iter()
+            .map(|action| self.step(action))
+            .collect()
+    }
+
+    pub fn observe(&self) -> Observation {
+        self.observe_since(self.game_state.message_log().len())
+    }
+
+    fn observe_since(&self, first_message_index: usize) -> Observation {
+        let game_state = &self.game_state;
+        let visible_cells = game_state
+            .size()
+            .coord_iter_row_major()
+            .filter(|&coord| game_state.cell_visibility(coord) == CellVisibility::Currently)
+            .collect();
+        Observation {
+            is_player_alive: game_state.is_player_alive(),
+            player_coord: game_state.player_coord(),
+            player_hit_points: game_state.player_hit_points(),
+            dungeon_level: game_state.dungeon_level(),
+            npcs: game_state
+                .npcs()
+                .map(|(npc_type, coord, hit_points)| NpcObservation {
+                    npc_type,
+                    coord,
+                    hit_points,
+                })
+                .collect(),
+            messages: game_state.message_log()[first_message_index..].to_vec(),
+            visible_cells,
+        }
+    }
+}
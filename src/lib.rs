@@ -0,0 +1,10 @@
+pub mod app;
+mod behaviour;
+pub mod game;
+pub mod headless;
+pub mod replay;
+pub mod save;
+pub mod terrain;
+mod ui;
+pub mod visibility;
+mod world;
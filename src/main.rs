@@ -1,22 +1,34 @@
-use app::App;
-use chargrid_graphical::{Context, ContextDescriptor, Dimensions, FontBytes};
 use coord_2d::Size;
 use rand::Rng;
+use rustoguelike::app::{self, App, ErrorLog};
+use rustoguelike::game::GameState;
+use rustoguelike::replay::{Recorder, Replay, ReplaySpeed};
+use rustoguelike::save::{self, LoadError};
+use rustoguelike::terrain::GeneratorChoice;
+use rustoguelike::visibility::VisibilityAlgorithm;
 use simon::Arg;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+use terminal::ColourDepth;
 
-mod app;
-mod behaviour;
-mod game;
-mod terrain;
-mod ui;
-mod visibility;
-mod world;
+#[cfg(feature = "graphical")]
+mod graphical;
+mod terminal;
 
-use visibility::VisibilityAlgorithm;
+#[cfg(not(any(feature = "graphical", feature = "ansi_terminal")))]
+compile_error!("at least one of the \"graphical\" and \"ansi_terminal\" features must be enabled");
 
 struct Args {
     rng_seed: u64,
+    generator_choice: GeneratorChoice,
     visibility_algorithm: VisibilityAlgorithm,
+    save_file: PathBuf,
+    record_file: Option<PathBuf>,
+    replay_file: Option<PathBuf>,
+    replay_speed: ReplaySpeed,
+    terminal: bool,
+    colour_depth: ColourDepth,
 }
 
 impl Args {
@@ -25,53 +37,159 @@ impl Args {
             let {
                 rng_seed = simon::opt("r", "rng-seed", "seed for random number generator", "INT")
                     .with_default_lazy(|| rand::thread_rng().gen());
+                generator_choice = simon::opt("g", "generator",
+                    "level generator (by-level, rooms, bsp, caves or drunkards-walk)", "NAME")
+                    .with_default(GeneratorChoice::ByDungeonLevel);
                 visibility_algorithm = simon::flag("", "debug-omniscient", "enable omniscience")
                     .map(|omniscient| if omniscient {
                         VisibilityAlgorithm::Omniscient
                     } else {
                         VisibilityAlgorithm::Shadowcast
                     });
+                save_file = simon::opt("s", "save-file", "path to save file", "PATH")
+                    .with_default(PathBuf::from("rustoguelike.save"));
+                record_file = simon::opt("", "record",
+                    "record the seed and every action to a file (disables the save file)", "PATH");
+                replay_file = simon::opt("", "replay",
+                    "replay a recording made with --record (disables the save file)", "PATH");
+                replay_step = simon::flag("", "replay-step",
+                    "perform one replayed action per key press");
+                replay_delay_ms = simon::opt("", "replay-delay",
+                    "milliseconds between replayed actions", "INT")
+                    .with_default(100);
+                terminal = simon::flag("", "terminal",
+                    "run in the terminal instead of opening a window");
+                colour_depth = simon::opt("", "colour",
+                    "colours supported by the terminal (auto, 24bit, 256, 16 or none)", "DEPTH")
+                    .with_default(ColourDepth::Auto);
             } in {
-                Self { rng_seed, visibility_algorithm }
+                Self {
+                    rng_seed,
+                    generator_choice,
+                    visibility_algorithm,
+                    save_file,
+                    record_file,
+                    replay_file,
+                    replay_speed: if replay_step {
+                        ReplaySpeed::Step
+                    } else {
+                        ReplaySpeed::Delay(Duration::from_millis(replay_delay_ms))
+                    },
+                    // A build without the graphical frontend always runs in the terminal
+                    terminal: terminal || !cfg!(feature = "graphical"),
+                    colour_depth,
+                }
             }
         }
     }
 }
 
+// Returns `None` if there is no saved game, and exits if the save file can't be loaded
+fn load_saved_game(save_file: &Path) -> Option<GameState> {
+    match save::load_game(save_file) {
+        Ok(game_state) => {
+            println!("Resuming game from {}", save_file.display());
+            Some(game_state)
+        }
+        Err(LoadError::NoSaveFile) => None,
+        Err(error) => {
+            eprintln!("Failed to load {}: {}", save_file.display(), error);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(feature = "graphical")]
+fn run_graphical(app: App) {
+    graphical::run(app);
+}
+
+#[cfg(not(feature = "graphical"))]
+fn run_graphical(_app: App) {
+    unreachable!("the terminal frontend is always used without the graphical feature");
+}
+
+#[cfg(feature = "ansi_terminal")]
+fn run_terminal(app: App, colour_depth: ColourDepth) {
+    terminal::run(app, colour_depth);
+}
+
+#[cfg(not(feature = "ansi_terminal"))]
+fn run_terminal(_app: App, _colour_depth: ColourDepth) {
+    eprintln!("This build doesn't include the terminal frontend");
+    process::exit(1);
+}
+
 fn main() {
     let Args {
         rng_seed,
+        generator_choice,
         visibility_algorithm,
+        save_file,
+        record_file,
+        replay_file,
+        replay_speed,
+        terminal,
+        colour_depth,
     } = Args::parser().with_help_default().parse_env_or_exit();
-    println!("RNG Seed: {}", rng_seed);
-    const CELL_SIZE_PX: f64 = 24.;
-    let context = Context::new(ContextDescriptor {
-        font_bytes: FontBytes {
-            normal: include_bytes!("./fonts/PxPlus_IBM_CGAthin.ttf").to_vec(),
-            bold: include_bytes!("./fonts/PxPlus_IBM_CGA.ttf").to_vec(),
-        },
-        title: "Rustoguelike".to_string(),
-        window_dimensions: Dimensions {
-            width: 960.,
-            height: 720.,
-        },
-        cell_dimensions: Dimensions {
-            width: CELL_SIZE_PX,
-            height: CELL_SIZE_PX,
-        },
-        font_dimensions: Dimensions {
-            width: CELL_SIZE_PX,
-            height: CELL_SIZE_PX,
-        },
-        font_source_dimensions: Dimensions {
-            width: CELL_SIZE_PX as f32,
-            height: CELL_SIZE_PX as f32,
-        },
-        underline_width: 0.1,
-        underline_top_offset: 0.8,
-    })
-    .expect("Failed to initialize graphical context");
     let screen_size = Size::new(40, 30);
-    let app = App::new(screen_size, rng_seed, visibility_algorithm);
-    context.run_app(app);
+    let replay = replay_file.map(|replay_file| {
+        Replay::load(&replay_file).unwrap_or_else(|error| {
+            eprintln!("Failed to load {}: {}", replay_file.display(), error);
+            process::exit(1);
+        })
+    });
+    // A replay always starts from a new game with the recorded seed and generator
+    let rng_seed = replay.as_ref().map(Replay::rng_seed).unwrap_or(rng_seed);
+    let generator_choice = replay
+        .as_ref()
+        .map(Replay::generator_choice)
+        .unwrap_or(generator_choice);
+    // Recorded and replayed games must be reproducible from their seed, so they always start a
+    // new game and neither load nor write the save file
+    let save_file = if record_file.is_none() && replay.is_none() {
+        Some(save_file)
+    } else {
+        None
+    };
+    let loaded_game_state = save_file.as_deref().and_then(load_saved_game);
+    let game_state = loaded_game_state.unwrap_or_else(|| {
+        println!("RNG Seed: {}", rng_seed);
+        GameState::new(
+            app::game_area_size(screen_size),
+            rng_seed,
+            generator_choice,
+            visibility_algorithm,
+        )
+    });
+    let recorder = record_file.map(|record_file| {
+        Recorder::create(&record_file, rng_seed, generator_choice).unwrap_or_else(|error| {
+            eprintln!("Failed to create {}: {}", record_file.display(), error);
+            process::exit(1);
+        })
+    });
+    // The graphical frontend never returns, but leaves the terminal free to print errors to as
+    // they happen
+    let error_log = if terminal {
+        Some(ErrorLog::default())
+    } else {
+        None
+    };
+    let app = App::new(
+        screen_size,
+        game_state,
+        visibility_algorithm,
+        save_file,
+        recorder,
+        replay.map(|replay| (replay, replay_speed)),
+        error_log.clone(),
+    );
+    if terminal {
+        run_terminal(app, colour_depth);
+    } else {
+        run_graphical(app);
+    }
+    if let Some(error_log) = error_log {
+        error_log.report();
+    }
 }
@@ -0,0 +1,207 @@
+use crate::game::GameAction;
+use crate::terrain::GeneratorChoice;
+use crate::world::LevelUpChoice;
+use direction::CardinalDirection;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+
+// A recording is a text file. The first line is a header identifying the format, the second
+// holds the rng seed, the third names the level generator, and each remaining line is a single
+// action, e.g.
+//
+//     rustoguelike-recording 2
+//     seed 1234
+//     generator by-level
+//     move north
+//     get
+//     use 0
+//     level-up attack
+const HEADER: &str = "rustoguelike-recording 2";
+
+fn direction_name(direction: CardinalDirection) -> &'static str {
+    match direction {
+        CardinalDirection::North => "north",
+        CardinalDirection::East => "east",
+        CardinalDirection::South => "south",
+        CardinalDirection::West => "west",
+    }
+}
+
+fn level_up_choice_name(choice: LevelUpChoice) -> &'static str {
+    match choice {
+        LevelUpChoice::MaxHitPoints => "max-hit-points",
+        LevelUpChoice::Attack => "attack",
+        LevelUpChoice::Defence => "defence",
+    }
+}
+
+fn write_action<W: Write>(writer: &mut W, action: GameAction) -> io::Result<()> {
+    match action {
+        GameAction::Move(direction) => writeln!(writer, "move {}", direction_name(direction)),
+        GameAction::Wait => writeln!(writer, "wait"),
+        GameAction::GetItem => writeln!(writer, "get"),
+        GameAction::UseItem(inventory_index) => writeln!(writer, "use {}", inventory_index),
+        GameAction::DropItem(inventory_index) => writeln!(writer, "drop {}", inventory_index),
+        GameAction::Descend => writeln!(writer, "descend"),
+        GameAction::LevelUp(choice) => {
+            writeln!(writer, "level-up {}", level_up_choice_name(choice))
+        }
+    }
+}
+
+fn parse_action(line: &str) -> Option<GameAction> {
+    let mut words = line.split_whitespace();
+    let action = match words.next()? {
+        "move" => {
+            let direction = match words.next()? {
+                "north" => CardinalDirection::North,
+                "east" => CardinalDirection::East,
+                "south" => CardinalDirection::South,
+                "west" => CardinalDirection::West,
+                _ => return None,
+            };
+            GameAction::Move(direction)
+        }
+        "wait" => GameAction::Wait,
+        "get" => GameAction::GetItem,
+        "use" => GameAction::UseItem(words.next()?.parse().ok()?),
+        "drop" => GameAction::DropItem(words.next()?.parse().ok()?),
+        "descend" => GameAction::Descend,
+        "level-up" => {
+            let word = words.next()?;
+            let &choice = LevelUpChoice::ALL
+                .iter()
+                .find(|&&choice| level_up_choice_name(choice) == word)?;
+            GameAction::LevelUp(choice)
+        }
+        _ => return None,
+    };
+    if words.next().is_some() {
+        return None;
+    }
+    Some(action)
+}
+
+// Writes each action to a file as soon as it is performed, so the recording is complete even
+// if the game crashes
+pub struct Recorder {
+    file: File,
+}
+
+impl Recorder {
+    pub fn create(
+        path: &Path,
+        rng_seed: u64,
+        generator_choice: GeneratorChoice,
+    ) -> io::Result<Self> {
+        let mut file = File::create(path)?;
+        writeln!(file, "{}", HEADER)?;
+        writeln!(file, "seed {}", rng_seed)?;
+        writeln!(file, "generator {}", generator_choice)?;
+        Ok(Self { file })
+    }
+
+    pub fn record(&mut self, action: GameAction) -> io::Result<()> {
+        write_action(&mut self.file, action)?;
+        self.file.flush()
+    }
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    InvalidHeader,
+    InvalidLine { line_number: usize, line: String },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "failed to read recording: {}", error),
+            Self::InvalidHeader => write!(f, "file is not a recording (expected \"{}\")", HEADER),
+            Self::InvalidLine { line_number, line } => {
+                write!(f, "invalid line {} in recording: {:?}", line_number, line)
+            }
+        }
+    }
+}
+
+pub struct Replay {
+    rng_seed: u64,
+    generator_choice: GeneratorChoice,
+    actions: Vec<GameAction>,
+    next_index: usize,
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> Result<Self, ReplayError> {
+        let contents = fs::read_to_string(path).map_err(ReplayError::Io)?;
+        let mut lines = contents.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(ReplayError::InvalidHeader);
+        }
+        let rng_seed = lines
+            .next()
+            .and_then(|line| line.strip_prefix("seed "))
+            .and_then(|seed| seed.parse().ok())
+            .ok_or_else(|| ReplayError::InvalidLine {
+                line_number: 2,
+                line: contents.lines().nth(1).unwrap_or("").to_string(),
+            })?;
+        let generator_choice = lines
+            .next()
+            .and_then(|line| line.strip_prefix("generator "))
+            .and_then(|generator| generator.parse().ok())
+            .ok_or_else(|| ReplayError::InvalidLine {
+                line_number: 3,
+                line: contents.lines().nth(2).unwrap_or("").to_string(),
+            })?;
+        let mut actions = Vec::new();
+        // The first three lines are the header, seed and generator, and line numbers start at 1
+        for (line_number, line) in (4..).zip(lines) {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let action = parse_action(line).ok_or_else(|| ReplayError::InvalidLine {
+                line_number,
+                line: line.to_string(),
+            })?;
+            actions.push(action);
+        }
+        Ok(Self {
+            rng_seed,
+            generator_choice,
+            actions,
+            next_index: 0,
+        })
+    }
+
+    pub fn rng_seed(&self) -> u64 {
+        self.rng_seed
+    }
+
+    pub fn generator_choice(&self) -> GeneratorChoice {
+        self.generator_choice
+    }
+
+    pub fn next_action(&mut self) -> Option<GameAction> {
+        let action = self.actions.get(self.next_index).cloned()?;
+        self.next_index += 1;
+        Some(action)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next_index >= self.actions.len()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum ReplaySpeed {
+    // Perform an action each time this much time has passed
+    Delay(Duration),
+    // Perform an action each time a key is pressed
+    Step,
+}
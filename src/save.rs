@@ -0,0 +1,163 @@
+use crate::game::GameState;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+// Every save file starts with these bytes, followed by the format version
+const MAGIC: &[u8; 8] = b"RUSTOGUE";
+
+// Increment this whenever a change to `GameState` (or anything it contains) changes how it is
+// serialized, so that old save files are rejected rather than misinterpreted
+const SAVE_FORMAT_VERSION: u32 = 8;
+
+#[derive(Debug)]
+pub enum LoadError {
+    NoSaveFile,
+    Io(io::Error),
+    NotASaveFile,
+    IncompatibleVersion { found: u32 },
+    Corrupt(bincode::Error),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NoSaveFile => write!(f, "save file does not exist"),
+            Self::Io(error) => write!(f, "failed to read save file: {}", error),
+            Self::NotASaveFile => write!(f, "file is not a save file"),
+            Self::IncompatibleVersion { found } => write!(
+                f,
+                "save file has format version {} but this version of the game can only load \
+                 version {} (delete the save file to start a new game)",
+                found, SAVE_FORMAT_VERSION
+            ),
+            Self::Corrupt(error) => write!(f, "save file is corrupt: {}", error),
+        }
+    }
+}
+
+pub fn save_game(game_state: &GameState, path: &Path) -> io::Result<()> {
+    // Write to a temporary file first so a failed save can't clobber an existing one
+    let tmp_path = path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&SAVE_FORMAT_VERSION.to_le_bytes())?;
+    bincode::serialize_into(&mut writer, game_state).map_err(io::Error::other)?;
+    writer.flush()?;
+    drop(writer);
+    fs::rename(tmp_path, path)
+}
+
+pub fn load_game(path: &Path) -> Result<GameState, LoadError> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Err(LoadError::NoSaveFile),
+        Err(error) => return Err(LoadError::Io(error)),
+    };
+    let mut reader = BufReader::new(file);
+    let mut magic = [0; 8];
+    let mut version = [0; 4];
+    if reader.read_exact(&mut magic).is_err() || &magic != MAGIC {
+        return Err(LoadError::NotASaveFile);
+    }
+    reader.read_exact(&mut version).map_err(LoadError::Io)?;
+    let version = u32::from_le_bytes(version);
+    if version != SAVE_FORMAT_VERSION {
+        return Err(LoadError::IncompatibleVersion { found: version });
+    }
+    let mut game_state: GameState =
+        bincode::deserialize_from(reader).map_err(LoadError::Corrupt)?;
+    game_state.rebuild_unsaved_state();
+    Ok(game_state)
+}
+
+pub fn delete_save(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::GameAction;
+    use crate::terrain::GeneratorChoice;
+    use crate::visibility::VisibilityAlgorithm;
+    use coord_2d::Size;
+    use direction::CardinalDirection;
+    use std::path::PathBuf;
+    use std::process;
+
+    // Each test uses its own file, so tests running at the same time don't interfere
+    fn temp_save_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rustoguelike-{}-{}.save", name, process::id()))
+    }
+
+    fn play(game_state: &mut GameState, num_actions: usize) {
+        let actions = [
+            GameAction::Move(CardinalDirection::North),
+            GameAction::Move(CardinalDirection::East),
+            GameAction::GetItem,
+            GameAction::Move(CardinalDirection::South),
+            GameAction::Wait,
+            GameAction::Move(CardinalDirection::West),
+            GameAction::UseItem(0),
+            GameAction::Descend,
+        ];
+        for &action in actions.iter().cycle().take(num_actions) {
+            if !game_state.is_player_alive() {
+                break;
+            }
+            game_state.perform_action(action);
+            game_state.update_visibility(VisibilityAlgorithm::Shadowcast);
+        }
+    }
+
+    #[test]
+    fn loaded_games_continue_exactly_as_they_would_have() {
+        let path = temp_save_path("round-trip");
+        for rng_seed in 0..5 {
+            let mut game_state = GameState::new(
+                Size::new(40, 25),
+                rng_seed,
+                GeneratorChoice::default(),
+                VisibilityAlgorithm::Shadowcast,
+            );
+            play(&mut game_state, 100);
+            save_game(&game_state, &path).unwrap();
+            let mut loaded = load_game(&path).unwrap();
+            let serialize = |game_state: &GameState| bincode::serialize(game_state).unwrap();
+            assert_eq!(serialize(&loaded), serialize(&game_state));
+            play(&mut game_state, 100);
+            play(&mut loaded, 100);
+            assert_eq!(serialize(&loaded), serialize(&game_state));
+        }
+        delete_save(&path).unwrap();
+    }
+
+    #[test]
+    fn saves_from_other_versions_are_rejected() {
+        let path = temp_save_path("old-version");
+        let mut contents = MAGIC.to_vec();
+        contents.extend_from_slice(&7u32.to_le_bytes());
+        contents.extend_from_slice(&[0; 16]);
+        fs::write(&path, contents).unwrap();
+        let result = load_game(&path);
+        delete_save(&path).unwrap();
+        assert!(matches!(
+            result,
+            Err(LoadError::IncompatibleVersion { found: 7 })
+        ));
+    }
+
+    #[test]
+    fn files_without_the_magic_bytes_are_rejected() {
+        let path = temp_save_path("not-a-save");
+        fs::write(&path, b"NOTASAVE\x08\x00\x00\x00").unwrap();
+        let result = load_game(&path);
+        delete_save(&path).unwrap();
+        assert!(matches!(result, Err(LoadError::NotASaveFile)));
+    }
+}
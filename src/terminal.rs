@@ -0,0 +1,86 @@
+#[cfg(feature = "ansi_terminal")]
+use chargrid_ansi_terminal::{col_encode, Context};
+#[cfg(feature = "ansi_terminal")]
+use rustoguelike::app::App;
+use std::fmt;
+use std::str::FromStr;
+
+// How many colours the terminal can display. Colours are approximated with the nearest colour
+// the terminal supports, so the game stays playable on terminals with a limited palette.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColourDepth {
+    // Guess from the environment
+    Auto,
+    TrueColour,
+    Palette256,
+    Ansi16,
+    Monochrome,
+}
+
+#[cfg(feature = "ansi_terminal")]
+impl ColourDepth {
+    // Most terminals that support true colour set `COLORTERM`, and most 256-colour terminals
+    // advertise it in `TERM` (e.g. "xterm-256color"). Anything else gets the 16 standard colours.
+    fn detect() -> Self {
+        if let Ok(colorterm) = std::env::var("COLORTERM") {
+            if colorterm == "truecolor" || colorterm == "24bit" {
+                return Self::TrueColour;
+            }
+        }
+        match std::env::var("TERM") {
+            Ok(term) if term == "dumb" => Self::Monochrome,
+            Ok(term) if term.contains("256color") => Self::Palette256,
+            _ => Self::Ansi16,
+        }
+    }
+
+    fn resolve(self) -> Self {
+        match self {
+            Self::Auto => Self::detect(),
+            other => other,
+        }
+    }
+}
+
+impl FromStr for ColourDepth {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "24bit" => Ok(Self::TrueColour),
+            "256" => Ok(Self::Palette256),
+            "16" => Ok(Self::Ansi16),
+            "none" => Ok(Self::Monochrome),
+            _ => Err(format!(
+                "unknown colour depth {:?} (expected auto, 24bit, 256, 16 or none)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ColourDepth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Self::Auto => "auto",
+            Self::TrueColour => "24bit",
+            Self::Palette256 => "256",
+            Self::Ansi16 => "16",
+            Self::Monochrome => "none",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[cfg(feature = "ansi_terminal")]
+pub fn run(app: App, colour_depth: ColourDepth) {
+    let context = Context::new().expect("Failed to initialize terminal");
+    match colour_depth.resolve() {
+        ColourDepth::Auto | ColourDepth::TrueColour => {
+            context.run_app(app, col_encode::XtermTrueColour)
+        }
+        ColourDepth::Palette256 => context.run_app(app, col_encode::FromTermInfoRgb),
+        ColourDepth::Ansi16 => context.run_app(app, col_encode::FromTermInfoAnsi16Colour),
+        ColourDepth::Monochrome => context.run_app(app, col_encode::NoColour),
+    }
+}
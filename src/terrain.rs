@@ -1,155 +1,645 @@
+use crate::behaviour::Archetype;
 use crate::world::{ItemType, NpcType};
+use direction::CardinalDirection;
 use grid_2d::{Coord, Grid, Size};
+#[cfg(test)]
+pub use prefab::parse_level;
+use prefab::{Prefab, StampedPrefab};
 use rand::{seq::IteratorRandom, seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+
+mod bsp;
+mod caves;
+mod drunkards_walk;
+mod prefab;
+mod rooms;
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum TerrainTile {
     Player,
     Floor,
     Wall,
+    // A closed door
+    Door,
+    // Partially block sight, but can be walked through
+    TallGrass,
+    Smoke,
+    // Blocks movement, but not sight
+    GlassWall,
+    // A wall with a torch on it, which lights up its surroundings
+    Torch,
+    Stairs,
     Npc(NpcType),
+    // An npc which behaves differently from the usual behaviour of its type
+    NpcWithArchetype(NpcType, Archetype),
     Item(ItemType),
 }
 
-// A rectangular area of the map
-struct Room {
-    top_left: Coord,
-    size: Size,
+impl TerrainTile {
+    fn is_passable(self) -> bool {
+        !matches!(self, Self::Wall | Self::GlassWall | Self::Torch)
+    }
 }
 
-impl Room {
-    // Returns a randomly sized room at a random position within `bounds`
-    fn choose<R: Rng>(bounds: Size, rng: &mut R) -> Self {
-        let width = rng.gen_range(5, 11);
-        let height = rng.gen_range(5, 9);
-        let size = Size::new(width, height);
-        let top_left_bounds = bounds - size;
-        let left = rng.gen_range(0, top_left_bounds.width());
-        let top = rng.gen_range(0, top_left_bounds.height());
-        let top_left = Coord::new(left as i32, top as i32);
-        Self { top_left, size }
-    }
+// Describes how densely a level is populated with npcs and items
+pub struct SpawnTable {
+    // Each room gets a number of npcs chosen uniformly from this list
+    pub npcs_per_room_distribution: &'static [usize],
+    // Percent chance that an npc is a troll or an archer rather than an orc
+    pub troll_percent: u32,
+    pub archer_percent: u32,
+    // Each room gets a number of health potions chosen uniformly from this list
+    pub health_potions_per_room_distribution: &'static [usize],
+}
+
+// Spawn tables indexed by dungeon level, starting at level 1. Levels deeper than the last
+// entry use the last entry.
+const SPAWN_TABLES: &[SpawnTable] = &[
+    SpawnTable {
+        npcs_per_room_distribution: &[0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 3, 3, 4],
+        troll_percent: 20,
+        archer_percent: 0,
+        health_potions_per_room_distribution: &[0, 0, 1, 1, 1, 1, 1, 2, 2],
+    },
+    SpawnTable {
+        npcs_per_room_distribution: &[0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 3, 3, 3, 4, 4],
+        troll_percent: 30,
+        archer_percent: 10,
+        health_potions_per_room_distribution: &[0, 0, 0, 1, 1, 1, 1, 2],
+    },
+    SpawnTable {
+        npcs_per_room_distribution: &[0, 0, 0, 1, 1, 1, 2, 2, 2, 3, 3, 3, 4, 4, 4, 5],
+        troll_percent: 40,
+        archer_percent: 15,
+        health_potions_per_room_distribution: &[0, 0, 0, 0, 1, 1, 1, 2],
+    },
+    SpawnTable {
+        npcs_per_room_distribution: &[0, 0, 1, 1, 2, 2, 2, 3, 3, 3, 4, 4, 4, 5, 5, 6],
+        troll_percent: 55,
+        archer_percent: 15,
+        health_potions_per_room_distribution: &[0, 0, 0, 0, 0, 1, 1, 1, 2],
+    },
+    SpawnTable {
+        npcs_per_room_distribution: &[0, 1, 1, 2, 2, 3, 3, 3, 4, 4, 4, 5, 5, 5, 6, 6],
+        troll_percent: 70,
+        archer_percent: 15,
+        health_potions_per_room_distribution: &[0, 0, 0, 0, 0, 0, 1, 1, 2],
+    },
+];
 
-    // Returns a coord at the centre of the room, rounding down
-    fn centre(&self) -> Coord {
-        self.top_left + self.size.to_coord().unwrap() / 2
+impl SpawnTable {
+    pub fn for_dungeon_level(dungeon_level: u32) -> &'static Self {
+        let index = (dungeon_level.max(1) - 1) as usize;
+        SPAWN_TABLES
+            .get(index)
+            .unwrap_or_else(|| SPAWN_TABLES.last().unwrap())
     }
+}
+
+// Generates the terrain for a level, including where the player starts and where the stairs,
+// npcs and items are placed
+pub trait Generator {
+    fn generate<R: Rng>(
+        &self,
+        size: Size,
+        spawn_table: &SpawnTable,
+        rng: &mut R,
+    ) -> Grid<TerrainTile>;
+}
 
-    // Returns an iterator over all the coordinates in the room in row major order
-    fn coords<'a>(&'a self) -> impl 'a + Iterator<Item = Coord> {
-        self.size
-            .coord_iter_row_major()
-            .map(move |coord| self.top_left + coord)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GeneratorKind {
+    // Rooms placed at random wherever they fit, joined by corridors
+    Rooms,
+    // Rooms placed by recursively splitting the map in two, binary space partitioning style
+    Bsp,
+    // Caves grown with a cellular automaton
+    Caves,
+    // Tunnels dug by a random walk
+    DrunkardsWalk,
+}
+
+impl GeneratorKind {
+    pub const ALL: [Self; 4] = [Self::Rooms, Self::Bsp, Self::Caves, Self::DrunkardsWalk];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Rooms => "rooms",
+            Self::Bsp => "bsp",
+            Self::Caves => "caves",
+            Self::DrunkardsWalk => "drunkards-walk",
+        }
     }
+}
 
-    // Returns true if and only if each cell of `grid` overlapping this room is `None`
-    fn only_intersects_empty(&self, grid: &Grid<Option<TerrainTile>>) -> bool {
-        self.coords().all(|coord| grid.get_checked(coord).is_none())
+impl Generator for GeneratorKind {
+    fn generate<R: Rng>(
+        &self,
+        size: Size,
+        spawn_table: &SpawnTable,
+        rng: &mut R,
+    ) -> Grid<TerrainTile> {
+        match self {
+            Self::Rooms => rooms::Rooms.generate(size, spawn_table, rng),
+            Self::Bsp => bsp::Bsp.generate(size, spawn_table, rng),
+            Self::Caves => caves::Caves.generate(size, spawn_table, rng),
+            Self::DrunkardsWalk => drunkards_walk::DrunkardsWalk.generate(size, spawn_table, rng),
+        }
     }
+}
 
-    // Updates `grid`, setting each cell overlapping this room to `Some(TerrainTile::Floor)`.
-    // The top and left sides of the room are set to `Some(TerrainTile::Wall)` instead.
-    // This prevents a pair of rooms being placed immediately adjacent to one another.
-    fn carve_out(&self, grid: &mut Grid<Option<TerrainTile>>) {
-        for coord in self.coords() {
-            let cell = grid.get_checked_mut(coord);
-            if coord.x == self.top_left.x || coord.y == self.top_left.y {
-                *cell = Some(TerrainTile::Wall);
-            } else {
-                *cell = Some(TerrainTile::Floor);
+// Which generator is used for each level
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GeneratorChoice {
+    // Each level uses the next generator in `GeneratorKind::ALL`, starting with rooms on level 1
+    #[default]
+    ByDungeonLevel,
+    // Every level uses the same generator
+    Always(GeneratorKind),
+}
+
+impl GeneratorChoice {
+    pub fn for_dungeon_level(self, dungeon_level: u32) -> GeneratorKind {
+        match self {
+            Self::ByDungeonLevel => {
+                let index = (dungeon_level.max(1) - 1) as usize;
+                GeneratorKind::ALL[index % GeneratorKind::ALL.len()]
             }
+            Self::Always(kind) => kind,
         }
     }
+}
 
-    // Place `n` randomly chosen NPCs at random positions within the room
-    fn place_npcs<R: Rng>(&self, n: usize, grid: &mut Grid<Option<TerrainTile>>, rng: &mut R) {
-        for coord in self
-            .coords()
-            .filter(|&coord| grid.get_checked(coord).unwrap() == TerrainTile::Floor)
-            .choose_multiple(rng, n)
-        {
-            let npc_type = if rng.gen_range(0, 100) < 80 {
-                NpcType::Orc
-            } else {
-                NpcType::Troll
-            };
-            *grid.get_checked_mut(coord) = Some(TerrainTile::Npc(npc_type));
+impl FromStr for GeneratorChoice {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "by-level" {
+            return Ok(Self::ByDungeonLevel);
         }
+        GeneratorKind::ALL
+            .iter()
+            .find(|kind| kind.name() == s)
+            .map(|&kind| Self::Always(kind))
+            .ok_or_else(|| {
+                format!(
+                    "unknown generator {:?} (expected by-level, rooms, bsp, caves or \
+                     drunkards-walk)",
+                    s
+                )
+            })
     }
+}
 
-    fn place_health_potions<R: Rng>(
-        &self,
-        n: usize,
-        grid: &mut Grid<Option<TerrainTile>>,
-        rng: &mut R,
-    ) {
-        for coord in self
-            .coords()
-            .filter(|&coord| grid.get_checked(coord).unwrap() == TerrainTile::Floor)
-            .choose_multiple(rng, n)
-        {
-            *grid.get_checked_mut(coord) = Some(TerrainTile::Item(ItemType::HealthPotion));
+impl fmt::Display for GeneratorChoice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::ByDungeonLevel => write!(f, "by-level"),
+            Self::Always(kind) => write!(f, "{}", kind.name()),
         }
     }
 }
 
+fn choose_npc_type<R: Rng>(spawn_table: &SpawnTable, rng: &mut R) -> NpcType {
+    let roll = rng.gen_range(0..100);
+    if roll < 100 - spawn_table.troll_percent - spawn_table.archer_percent {
+        NpcType::Orc
+    } else if roll < 100 - spawn_table.troll_percent {
+        NpcType::Archer
+    } else {
+        NpcType::Troll
+    }
+}
+
+fn is_on_edge(coord: Coord, size: Size) -> bool {
+    coord.x == 0
+        || coord.y == 0
+        || coord.x == size.width() as i32 - 1
+        || coord.y == size.height() as i32 - 1
+}
+
+// Returns the coordinates along an L-shaped corridor between a pair of coordinates
+fn corridor_coords(start: Coord, end: Coord) -> impl Iterator<Item = Coord> {
+    let horizontal = (start.x.min(end.x)..=start.x.max(end.x)).map(move |x| Coord { x, ..start });
+    let vertical = (start.y.min(end.y)..start.y.max(end.y)).map(move |y| Coord { y, ..end });
+    horizontal.chain(vertical)
+}
+
 // carve out an L-shaped corridor between a pair of coordinates
 fn carve_corridor(start: Coord, end: Coord, grid: &mut Grid<Option<TerrainTile>>) {
-    for i in start.x.min(end.x)..=start.x.max(end.x) {
-        let cell = grid.get_checked_mut(Coord { x: i, ..start });
-        if *cell == None || *cell == Some(TerrainTile::Wall) {
+    for coord in corridor_coords(start, end) {
+        let cell = grid.get_checked_mut(coord);
+        if cell.is_none() || *cell == Some(TerrainTile::Wall) {
             *cell = Some(TerrainTile::Floor);
         }
     }
-    for i in start.y.min(end.y)..start.y.max(end.y) {
-        let cell = grid.get_checked_mut(Coord { y: i, ..end });
-        if *cell == None || *cell == Some(TerrainTile::Wall) {
-            *cell = Some(TerrainTile::Floor);
+}
+
+// Returns a grid containing the number of steps needed to reach each cell from `start`, moving
+// between cardinally adjacent cells for which `is_passable` returns true. Cells that can't be
+// reached contain `None`.
+fn distances_from<T, F: Fn(&T) -> bool>(
+    start: Coord,
+    grid: &Grid<T>,
+    is_passable: F,
+) -> Grid<Option<u32>> {
+    let mut distances = Grid::new_copy(grid.size(), None);
+    *distances.get_checked_mut(start) = Some(0);
+    let mut to_visit = VecDeque::new();
+    to_visit.push_back((start, 0));
+    while let Some((coord, distance)) = to_visit.pop_front() {
+        for direction in CardinalDirection::all() {
+            let neighbour = coord + direction.coord();
+            if let Some(cell) = grid.get(neighbour) {
+                let neighbour_distance = distances.get_checked_mut(neighbour);
+                if neighbour_distance.is_none() && is_passable(cell) {
+                    *neighbour_distance = Some(distance + 1);
+                    to_visit.push_back((neighbour, distance + 1));
+                }
+            }
+        }
+    }
+    distances
+}
+
+// Levels that aren't made of rooms are populated as though each area of this many floor cells
+// were a room
+const FLOOR_CELLS_PER_AREA: usize = 40;
+
+// Places the player, the stairs, npcs and items on a level made of only floor and walls. The
+// stairs are placed as far from the player as possible.
+fn populate_open_level<R: Rng>(
+    player_coord: Coord,
+    spawn_table: &SpawnTable,
+    grid: &mut Grid<TerrainTile>,
+    rng: &mut R,
+) {
+    *grid.get_checked_mut(player_coord) = TerrainTile::Player;
+    let distances = distances_from(player_coord, grid, |&tile| tile.is_passable());
+    let furthest = distances
+        .enumerate()
+        .filter_map(|(coord, &distance)| Some((distance?, coord)))
+        .max_by_key(|&(distance, _)| distance);
+    if let Some((distance, stairs_coord)) = furthest {
+        if distance > 0 {
+            *grid.get_checked_mut(stairs_coord) = TerrainTile::Stairs;
         }
     }
+    let floor_coords = grid
+        .enumerate()
+        .filter(|&(_, &tile)| tile == TerrainTile::Floor)
+        .map(|(coord, _)| coord)
+        .collect::<Vec<_>>();
+    let num_areas = (floor_coords.len() / FLOOR_CELLS_PER_AREA).max(1);
+    let mut num_npcs = 0;
+    let mut num_health_potions = 0;
+    for _ in 0..num_areas {
+        num_npcs += spawn_table.npcs_per_room_distribution.choose(rng).unwrap();
+        num_health_potions += spawn_table
+            .health_potions_per_room_distribution
+            .choose(rng)
+            .unwrap();
+    }
+    let coords = floor_coords
+        .into_iter()
+        .choose_multiple(rng, num_npcs + num_health_potions);
+    for (i, coord) in coords.into_iter().enumerate() {
+        *grid.get_checked_mut(coord) = if i < num_npcs {
+            TerrainTile::Npc(choose_npc_type(spawn_table, rng))
+        } else {
+            TerrainTile::Item(ItemType::HealthPotion)
+        };
+    }
 }
 
-pub fn generate_dungeon<R: Rng>(size: Size, rng: &mut R) -> Grid<TerrainTile> {
-    let mut grid = Grid::new_copy(size, None);
-    let mut room_centres = Vec::new();
+// Returns the cells along the shortest route from `start` to a cell which `distances` says can
+// be reached, going through passable cells and digging through walls. Walls on the edge of the
+// map and walls that are part of a prefab aren't dug through, except that a route can go
+// straight through the outer wall of a prefab, where a door will be placed. Returns `None` if
+// there is no such route.
+fn route_to_reachable_area(
+    start: Coord,
+    grid: &Grid<TerrainTile>,
+    distances: &Grid<Option<u32>>,
+    prefabs: &[StampedPrefab],
+) -> Option<Vec<Coord>> {
+    let size = grid.size();
+    let is_outer_wall = |coord| prefabs.iter().any(|prefab| prefab.is_outer_wall(coord));
+    let can_enter = |from: Coord, to: Coord| {
+        let tile = *grid.get_checked(to);
+        if tile.is_passable() {
+            return true;
+        }
+        if is_on_edge(to, size) {
+            return false;
+        }
+        if prefabs.iter().any(|prefab| prefab.contains(to)) {
+            // Moving from one outer wall cell to another would run along the wall
+            return tile == TerrainTile::Wall && is_outer_wall(to) && !is_outer_wall(from);
+        }
+        true
+    };
+    let mut came_from = Grid::new_copy(size, None);
+    *came_from.get_checked_mut(start) = Some(start);
+    let mut to_visit = VecDeque::new();
+    to_visit.push_back(start);
+    while let Some(coord) = to_visit.pop_front() {
+        if distances.get_checked(coord).is_some() {
+            let mut route = vec![coord];
+            let mut coord = coord;
+            while coord != start {
+                coord = came_from.get_checked(coord).unwrap();
+                route.push(coord);
+            }
+            return Some(route);
+        }
+        for direction in CardinalDirection::all() {
+            let neighbour = coord + direction.coord();
+            if came_from.get(neighbour) == Some(&None) && can_enter(coord, neighbour) {
+                *came_from.get_checked_mut(neighbour) = Some(coord);
+                to_visit.push_back(neighbour);
+            }
+        }
+    }
+    None
+}
 
-    const NPCS_PER_ROOM_DISTRIBUTION: &[usize] = &[0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 3, 3, 4];
-    const HEALTH_POTIONS_PER_ROOM_DISTRIBUTION: &[usize] = &[0, 0, 1, 1, 1, 1, 1, 2, 2];
+// Makes sure the player can reach every floor tile (and everything placed on one) from where
+// they start. Any part of the map that can't be reached is connected to the rest of the level
+// with a corridor, which goes around prefabs and only enters one through a door in its outer
+// wall.
+fn connect_unreachable_areas(grid: &mut Grid<TerrainTile>, prefabs: &[StampedPrefab]) {
+    let is_passable = |&tile: &TerrainTile| tile.is_passable();
+    let player_coord = match grid
+        .enumerate()
+        .find(|&(_, &tile)| tile == TerrainTile::Player)
+    {
+        Some((coord, _)) => coord,
+        None => return,
+    };
+    loop {
+        let distances = distances_from(player_coord, grid, is_passable);
+        let unreachable_coord = grid
+            .enumerate()
+            .find(|&(coord, tile)| is_passable(tile) && distances.get_checked(coord).is_none())
+            .map(|(coord, _)| coord);
+        match unreachable_coord {
+            Some(coord) => {
+                // Only a part of a prefab that is walled off inside it has no route out, in
+                // which case a straight corridor is dug to the player regardless
+                let route = route_to_reachable_area(coord, grid, &distances, prefabs)
+                    .unwrap_or_else(|| corridor_coords(coord, player_coord).collect());
+                for coord in route {
+                    let is_outer_wall = prefabs.iter().any(|prefab| prefab.is_outer_wall(coord));
+                    let tile = grid.get_checked_mut(coord);
+                    if !tile.is_passable() {
+                        *tile = if is_outer_wall {
+                            TerrainTile::Door
+                        } else {
+                            TerrainTile::Floor
+                        };
+                    }
+                }
+            }
+            None => break,
+        }
+    }
+}
 
-    // Attempt to add a room a constant number of times
-    const NUM_ATTEMPTS: usize = 100;
-    for _ in 0..NUM_ATTEMPTS {
-        // Make a random room
-        let room = Room::choose(size, rng);
+// Levels get a torch for roughly every this many passable cells
+const PASSABLE_CELLS_PER_TORCH: usize = 60;
+// Torches are at least this far apart
+const MIN_TORCH_DISTANCE_SQUARED: u32 = 25;
 
-        // Carve out the room unless it overlaps with an existing room
-        if room.only_intersects_empty(&grid) {
-            room.carve_out(&mut grid);
+// Places torches on walls. A torch is only placed on a wall with exactly one passable cell next
+// to it, so its light spills into the level rather than through the wall.
+fn place_torches<R: Rng>(grid: &mut Grid<TerrainTile>, rng: &mut R) {
+    let size = grid.size();
+    let num_passable_cells = grid.iter().filter(|tile| tile.is_passable()).count();
+    let num_torches = num_passable_cells / PASSABLE_CELLS_PER_TORCH;
+    let num_passable_neighbours = |coord: Coord| {
+        CardinalDirection::all()
+            .filter(|direction| {
+                grid.get(coord + direction.coord())
+                    .is_some_and(|tile| tile.is_passable())
+            })
+            .count()
+    };
+    let mut candidates = grid
+        .enumerate()
+        .filter(|&(coord, &tile)| {
+            tile == TerrainTile::Wall
+                && !is_on_edge(coord, size)
+                && num_passable_neighbours(coord) == 1
+        })
+        .map(|(coord, _)| coord)
+        .collect::<Vec<_>>();
+    candidates.shuffle(rng);
+    let mut torches: Vec<Coord> = Vec::new();
+    for coord in candidates {
+        if torches.len() >= num_torches {
+            break;
+        }
+        if torches
+            .iter()
+            .all(|&torch| torch.distance2(coord) >= MIN_TORCH_DISTANCE_SQUARED)
+        {
+            *grid.get_checked_mut(coord) = TerrainTile::Torch;
+            torches.push(coord);
+        }
+    }
+}
+
+pub fn generate_dungeon<G: Generator, R: Rng>(
+    size: Size,
+    generator: &G,
+    spawn_table: &SpawnTable,
+    rng: &mut R,
+) -> Grid<TerrainTile> {
+    let mut grid = generator.generate(size, spawn_table, rng);
+    let prefabs = prefab::stamp_prefabs(Prefab::built_in(), &mut grid, rng);
+    connect_unreachable_areas(&mut grid, &prefabs);
+    place_torches(&mut grid, rng);
+    grid
+}
 
-            let room_centre = room.centre();
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_isaac::Isaac64Rng;
 
-            // Add the player to the centre of the room if it's the first room
-            if room_centres.is_empty() {
-                *grid.get_checked_mut(room_centre) = Some(TerrainTile::Player);
+    fn assert_all_reachable(grid: &Grid<TerrainTile>) {
+        let (player_coord, _) = grid
+            .enumerate()
+            .find(|&(_, &tile)| tile == TerrainTile::Player)
+            .expect("no player");
+        let distances = distances_from(player_coord, grid, |&tile| tile.is_passable());
+        for (coord, &tile) in grid.enumerate() {
+            if tile.is_passable() {
+                assert!(
+                    distances.get_checked(coord).is_some(),
+                    "{:?} is unreachable",
+                    coord
+                );
             }
+        }
+    }
+
+    fn check_generated_level(
+        size: Size,
+        generator: GeneratorKind,
+        rng_seed: u64,
+        dungeon_level: u32,
+    ) {
+        let mut rng = Isaac64Rng::seed_from_u64(rng_seed);
+        let spawn_table = SpawnTable::for_dungeon_level(dungeon_level);
+        let grid = generate_dungeon(size, &generator, spawn_table, &mut rng);
+        let level = format!(
+            "{} seed {} level {}",
+            generator.name(),
+            rng_seed,
+            dungeon_level
+        );
+        assert_all_reachable(&grid);
+        let num_players = grid
+            .iter()
+            .filter(|&&tile| tile == TerrainTile::Player)
+            .count();
+        assert_eq!(num_players, 1, "{}", level);
+        assert!(
+            grid.iter().any(|&tile| tile == TerrainTile::Stairs),
+            "{} has no stairs",
+            level
+        );
+        for (coord, &tile) in grid.enumerate() {
+            if is_on_edge(coord, size) {
+                assert!(
+                    tile == TerrainTile::Wall,
+                    "{:?} is open in {}",
+                    coord,
+                    level
+                );
+            }
+        }
+    }
+
+    // Levels are smaller than in the game so that thousands of them can be checked on every run.
+    // Each seed is used at one depth, taking turns between depths.
+    #[test]
+    fn every_floor_tile_is_reachable_from_the_player() {
+        for &generator in GeneratorKind::ALL.iter() {
+            for rng_seed in 0..2000 {
+                let dungeon_level = 1 + (rng_seed % 5) as u32;
+                check_generated_level(Size::new(24, 16), generator, rng_seed, dungeon_level);
+            }
+        }
+    }
 
-            // Build up a list of all room centres for use in constructing corridors
-            room_centres.push(room_centre);
+    // Checks every depth for every seed on levels the size of the game's. This takes a few
+    // minutes without optimisations, so it only runs with `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn every_floor_tile_is_reachable_from_the_player_on_full_sized_levels() {
+        for &generator in GeneratorKind::ALL.iter() {
+            for rng_seed in 0..2000 {
+                for dungeon_level in 1..=5 {
+                    check_generated_level(Size::new(40, 25), generator, rng_seed, dungeon_level);
+                }
+            }
+        }
+    }
 
-            // Add npcs to the room
-            let &num_npcs = NPCS_PER_ROOM_DISTRIBUTION.choose(rng).unwrap();
-            room.place_npcs(num_npcs, &mut grid, rng);
+    #[test]
+    fn unreachable_areas_are_connected() {
+        let mut grid = parse_level(&[
+            "##########",
+            "#@..#....#",
+            "#...#.!..#",
+            "#...######",
+            "#...#.o..#",
+            "##########",
+        ]);
+        connect_unreachable_areas(&mut grid, &[]);
+        assert_all_reachable(&grid);
+    }
 
-            // Add health potions to the room
-            let &num_health_potions = HEALTH_POTIONS_PER_ROOM_DISTRIBUTION.choose(rng).unwrap();
-            room.place_health_potions(num_health_potions, &mut grid, rng);
+    #[test]
+    fn levels_with_a_single_room_have_stairs() {
+        // There is rarely room for more than one room on a map this small, and never room to split
+        // it in two for bsp
+        let size = Size::new(12, 10);
+        for &generator in [GeneratorKind::Rooms, GeneratorKind::Bsp].iter() {
+            for rng_seed in 0..100 {
+                let mut rng = Isaac64Rng::seed_from_u64(rng_seed);
+                let grid = generator.generate(size, SpawnTable::for_dungeon_level(1), &mut rng);
+                let num_stairs = grid
+                    .iter()
+                    .filter(|&&tile| tile == TerrainTile::Stairs)
+                    .count();
+                assert_eq!(
+                    num_stairs,
+                    1,
+                    "{} level with seed {}",
+                    generator.name(),
+                    rng_seed
+                );
+            }
         }
     }
 
-    // Add corridors connecting every adjacent pair of room centres
-    for window in room_centres.windows(2) {
-        carve_corridor(window[0], window[1], &mut grid);
+    #[test]
+    fn caves_are_generated_on_maps_too_small_for_a_big_cave() {
+        // Caves on a map this shape are almost never big enough, so the generator has to settle
+        // for the largest one it finds
+        let size = Size::new(40, 6);
+        let mut rng = Isaac64Rng::seed_from_u64(0);
+        let grid = caves::Caves.generate(size, SpawnTable::for_dungeon_level(1), &mut rng);
+        let num_players = grid
+            .iter()
+            .filter(|&&tile| tile == TerrainTile::Player)
+            .count();
+        assert_eq!(num_players, 1);
     }
 
-    grid.map(|t| t.unwrap_or(TerrainTile::Wall))
+    #[test]
+    fn corridors_only_enter_prefabs_through_doors() {
+        let mut grid = parse_level(&[
+            "############",
+            "#@.........#",
+            "############",
+            "############",
+            "############",
+            "############",
+            "############",
+            "#.........o#",
+            "############",
+        ]);
+        // Each cell inside the prefab is walled off from the others
+        let prefab = Prefab::parse("#######\n#.#!#.#\n#######\n").unwrap();
+        let mut rng = Isaac64Rng::seed_from_u64(0);
+        let prefabs = prefab::stamp_prefabs(&[prefab], &mut grid, &mut rng);
+        let stamped = grid.clone();
+        connect_unreachable_areas(&mut grid, &prefabs);
+        assert_all_reachable(&grid);
+        for (coord, &tile) in grid.enumerate() {
+            if prefabs[0].contains(coord) && tile != *stamped.get_checked(coord) {
+                assert!(tile == TerrainTile::Door, "{:?}", coord);
+                assert!(prefabs[0].is_outer_wall(coord), "{:?}", coord);
+            }
+        }
+    }
+
+    #[test]
+    fn generator_choice_round_trips_through_its_name() {
+        let choices = GeneratorKind::ALL
+            .iter()
+            .map(|&kind| GeneratorChoice::Always(kind))
+            .chain(Some(GeneratorChoice::ByDungeonLevel));
+        for choice in choices {
+            assert_eq!(choice.to_string().parse(), Ok(choice));
+        }
+    }
 }
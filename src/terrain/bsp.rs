@@ -0,0 +1,139 @@
+use super::rooms::{self, Room};
+use super::{carve_corridor, Generator, SpawnTable, TerrainTile};
+use grid_2d::{Coord, Grid, Size};
+use rand::Rng;
+
+// An area is only split if both halves would be at least this big, so that each can hold a room
+const MIN_AREA_WIDTH: u32 = 7;
+const MIN_AREA_HEIGHT: u32 = 6;
+
+// Rooms are at least this big, including their top and left walls
+const MIN_ROOM_WIDTH: u32 = 5;
+const MIN_ROOM_HEIGHT: u32 = 5;
+const MAX_ROOM_WIDTH: u32 = 12;
+const MAX_ROOM_HEIGHT: u32 = 9;
+
+// A rectangular part of the map which is either split in two or holds a single room
+struct Area {
+    top_left: Coord,
+    size: Size,
+}
+
+impl Area {
+    // Splits the area in two, across its longer side where possible, returning `None` if the
+    // area is too small to split
+    fn split<R: Rng>(&self, rng: &mut R) -> Option<(Self, Self)> {
+        let can_split_x = self.size.width() >= MIN_AREA_WIDTH * 2;
+        let can_split_y = self.size.height() >= MIN_AREA_HEIGHT * 2;
+        let split_x = match (can_split_x, can_split_y) {
+            (false, false) => return None,
+            (true, false) => true,
+            (false, true) => false,
+            (true, true) => self.size.width() > self.size.height() * 2 || rng.gen(),
+        };
+        if split_x {
+            let width = rng.gen_range(MIN_AREA_WIDTH..=(self.size.width() - MIN_AREA_WIDTH));
+            Some((
+                Self {
+                    top_left: self.top_left,
+                    size: self.size.set_width(width),
+                },
+                Self {
+                    top_left: self.top_left + Coord::new(width as i32, 0),
+                    size: self.size.set_width(self.size.width() - width),
+                },
+            ))
+        } else {
+            let height = rng.gen_range(MIN_AREA_HEIGHT..=(self.size.height() - MIN_AREA_HEIGHT));
+            Some((
+                Self {
+                    top_left: self.top_left,
+                    size: self.size.set_height(height),
+                },
+                Self {
+                    top_left: self.top_left + Coord::new(0, height as i32),
+                    size: self.size.set_height(self.size.height() - height),
+                },
+            ))
+        }
+    }
+
+    // Returns a randomly sized room at a random position within the area
+    fn choose_room<R: Rng>(&self, rng: &mut R) -> Room {
+        let width = rng.gen_range(MIN_ROOM_WIDTH..=self.size.width().min(MAX_ROOM_WIDTH));
+        let height = rng.gen_range(MIN_ROOM_HEIGHT..=self.size.height().min(MAX_ROOM_HEIGHT));
+        let left = rng.gen_range(0..=(self.size.width() - width));
+        let top = rng.gen_range(0..=(self.size.height() - height));
+        Room {
+            top_left: self.top_left + Coord::new(left as i32, top as i32),
+            size: Size::new(width, height),
+        }
+    }
+
+    // Carves a room into each area that can't be split any further, adding it to `rooms`, and
+    // connects the rooms on either side of each split with a corridor. Returns the centre of one
+    // of the rooms in this area.
+    fn carve_rooms<R: Rng>(
+        &self,
+        grid: &mut Grid<Option<TerrainTile>>,
+        rooms: &mut Vec<Room>,
+        rng: &mut R,
+    ) -> Coord {
+        match self.split(rng) {
+            None => {
+                let room = self.choose_room(rng);
+                room.carve_out(grid);
+                let centre = room.centre();
+                rooms.push(room);
+                centre
+            }
+            Some((first, second)) => {
+                let first_centre = first.carve_rooms(grid, rooms, rng);
+                let second_centre = second.carve_rooms(grid, rooms, rng);
+                carve_corridor(first_centre, second_centre, grid);
+                if rng.gen() {
+                    first_centre
+                } else {
+                    second_centre
+                }
+            }
+        }
+    }
+}
+
+// Recursively splits the map in two until each part is just big enough for a room, places a
+// room in each part, and connects the rooms in each pair of halves with a corridor
+pub struct Bsp;
+
+impl Generator for Bsp {
+    fn generate<R: Rng>(
+        &self,
+        size: Size,
+        spawn_table: &SpawnTable,
+        rng: &mut R,
+    ) -> Grid<TerrainTile> {
+        let mut grid = Grid::new_copy(size, None);
+        let mut rooms = Vec::new();
+        // Rooms have walls on their top and left sides, so leave space for a wall on the bottom
+        // and right edges of the map
+        let map_area = Area {
+            top_left: Coord::new(0, 0),
+            size: size - Size::new(1, 1),
+        };
+        map_area.carve_rooms(&mut grid, &mut rooms, rng);
+        for room in rooms.iter() {
+            room.place_doors(&mut grid);
+        }
+
+        // The player starts in the first room and the stairs are in the last, which are in
+        // opposite corners of the map
+        let player_coord = rooms.first().unwrap().centre();
+        *grid.get_checked_mut(player_coord) = Some(TerrainTile::Player);
+        rooms::place_stairs(&rooms, &mut grid);
+        for room in rooms.iter() {
+            room.populate(spawn_table, &mut grid, rng);
+        }
+
+        grid.map(|t| t.unwrap_or(TerrainTile::Wall))
+    }
+}
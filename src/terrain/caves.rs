@@ -0,0 +1,121 @@
+use super::{is_on_edge, populate_open_level, Generator, SpawnTable, TerrainTile};
+use direction::CardinalDirection;
+use grid_2d::{Coord, Grid, Size};
+use rand::{seq::IteratorRandom, seq::SliceRandom, Rng};
+
+// Percent chance that each cell starts as a wall
+const INITIAL_WALL_PERCENT: u32 = 45;
+const NUM_SMOOTHING_STEPS: usize = 5;
+// A cell becomes a wall if at least this many cells in the 3x3 square around it are walls
+const MIN_WALLS_TO_BECOME_WALL: usize = 5;
+// The map is generated again if the largest cave is smaller than this percentage of the map
+const MIN_CAVE_PERCENT: usize = 35;
+// After this many attempts, the largest cave generated so far is used even if it's too small
+const MAX_ATTEMPTS: usize = 100;
+const NUM_TALL_GRASS_PATCHES: usize = 4;
+const TALL_GRASS_PATCH_RADIUS_SQUARED: u32 = 8;
+// Percent chance that each floor cell within a patch of tall grass is covered in grass
+const TALL_GRASS_DENSITY_PERCENT: u32 = 70;
+
+// Each cell becomes a wall if most of the cells around it are walls, and floor otherwise. Cells
+// on the edge of the map are always walls.
+fn smooth(is_wall: &Grid<bool>) -> Grid<bool> {
+    let size = is_wall.size();
+    Grid::new_fn(size, |coord| {
+        if is_on_edge(coord, size) {
+            return true;
+        }
+        let mut num_walls = 0;
+        for y in (coord.y - 1)..=(coord.y + 1) {
+            for x in (coord.x - 1)..=(coord.x + 1) {
+                if *is_wall.get_checked(Coord::new(x, y)) {
+                    num_walls += 1;
+                }
+            }
+        }
+        num_walls >= MIN_WALLS_TO_BECOME_WALL
+    })
+}
+
+// Returns the cells of the largest group of cardinally connected floor cells
+fn largest_cave(is_wall: &Grid<bool>) -> Vec<Coord> {
+    let mut visited = Grid::new_copy(is_wall.size(), false);
+    let mut largest = Vec::new();
+    for (start, &start_is_wall) in is_wall.enumerate() {
+        if start_is_wall || *visited.get_checked(start) {
+            continue;
+        }
+        *visited.get_checked_mut(start) = true;
+        let mut cave = vec![start];
+        let mut index = 0;
+        while let Some(&coord) = cave.get(index) {
+            index += 1;
+            for direction in CardinalDirection::all() {
+                let neighbour = coord + direction.coord();
+                if is_wall.get(neighbour) == Some(&false) && !*visited.get_checked(neighbour) {
+                    *visited.get_checked_mut(neighbour) = true;
+                    cave.push(neighbour);
+                }
+            }
+        }
+        if cave.len() > largest.len() {
+            largest = cave;
+        }
+    }
+    largest
+}
+
+// Covers some of the empty floor in the cave with roughly circular patches of tall grass
+fn grow_tall_grass<R: Rng>(cave: &[Coord], grid: &mut Grid<TerrainTile>, rng: &mut R) {
+    for _ in 0..NUM_TALL_GRASS_PATCHES {
+        let &centre = cave.choose(rng).unwrap();
+        for &coord in cave {
+            let cell = grid.get_checked_mut(coord);
+            if *cell == TerrainTile::Floor
+                && coord.distance2(centre) <= TALL_GRASS_PATCH_RADIUS_SQUARED
+                && rng.gen_range(0..100) < TALL_GRASS_DENSITY_PERCENT
+            {
+                *cell = TerrainTile::TallGrass;
+            }
+        }
+    }
+}
+
+// Fills the map with random noise and smooths it with a cellular automaton, leaving open caves.
+// Only the largest cave is kept, and parts of it are overgrown with tall grass.
+pub struct Caves;
+
+impl Generator for Caves {
+    fn generate<R: Rng>(
+        &self,
+        size: Size,
+        spawn_table: &SpawnTable,
+        rng: &mut R,
+    ) -> Grid<TerrainTile> {
+        let min_cave_size = (size.count() * MIN_CAVE_PERCENT) / 100;
+        let mut cave = Vec::new();
+        for _ in 0..MAX_ATTEMPTS {
+            let mut is_wall = Grid::new_fn(size, |coord| {
+                is_on_edge(coord, size) || rng.gen_range(0..100) < INITIAL_WALL_PERCENT
+            });
+            for _ in 0..NUM_SMOOTHING_STEPS {
+                is_wall = smooth(&is_wall);
+            }
+            let candidate = largest_cave(&is_wall);
+            if candidate.len() > cave.len() {
+                cave = candidate;
+            }
+            if cave.len() >= min_cave_size {
+                break;
+            }
+        }
+        let mut grid = Grid::new_copy(size, TerrainTile::Wall);
+        for &coord in cave.iter() {
+            *grid.get_checked_mut(coord) = TerrainTile::Floor;
+        }
+        let &player_coord = cave.iter().choose(rng).unwrap();
+        populate_open_level(player_coord, spawn_table, &mut grid, rng);
+        grow_tall_grass(&cave, &mut grid, rng);
+        grid
+    }
+}
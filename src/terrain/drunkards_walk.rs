@@ -0,0 +1,45 @@
+use super::{populate_open_level, Generator, SpawnTable, TerrainTile};
+use direction::CardinalDirection;
+use grid_2d::{Coord, Grid, Size};
+use rand::{seq::IteratorRandom, Rng};
+
+// The walk stops once this percentage of the map has been dug out
+const FLOOR_PERCENT: usize = 40;
+
+// Starts in the middle of a map made entirely of walls, and digs out each cell it steps on while
+// walking in random directions. Everything dug out is connected by construction.
+pub struct DrunkardsWalk;
+
+impl Generator for DrunkardsWalk {
+    fn generate<R: Rng>(
+        &self,
+        size: Size,
+        spawn_table: &SpawnTable,
+        rng: &mut R,
+    ) -> Grid<TerrainTile> {
+        let mut grid = Grid::new_copy(size, TerrainTile::Wall);
+        // The walk stays off the edges of the map so the level is surrounded by walls
+        let interior_size = size - Size::new(2, 2);
+        let is_interior = |coord: Coord| (coord - Coord::new(1, 1)).is_valid(interior_size);
+        let target_num_floor_cells = (interior_size.count() * FLOOR_PERCENT) / 100;
+        let start = size.to_coord().unwrap() / 2;
+        let mut coord = start;
+        *grid.get_checked_mut(start) = TerrainTile::Floor;
+        let mut num_floor_cells = 1;
+        while num_floor_cells < target_num_floor_cells {
+            let direction = CardinalDirection::all().choose(rng).unwrap();
+            let next_coord = coord + direction.coord();
+            if !is_interior(next_coord) {
+                continue;
+            }
+            coord = next_coord;
+            let cell = grid.get_checked_mut(coord);
+            if *cell == TerrainTile::Wall {
+                *cell = TerrainTile::Floor;
+                num_floor_cells += 1;
+            }
+        }
+        populate_open_level(start, spawn_table, &mut grid, rng);
+        grid
+    }
+}
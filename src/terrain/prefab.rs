@@ -0,0 +1,413 @@
+use super::TerrainTile;
+use crate::behaviour::Archetype;
+use crate::world::{ItemType, NpcType};
+use grid_2d::{Coord, Grid, Size};
+use rand::{seq::IteratorRandom, seq::SliceRandom, Rng};
+use std::fmt;
+use std::sync::OnceLock;
+
+// A prefab is a hand-drawn part of a level written as text, one character per cell and one line
+// per row of cells. Every row must be the same width. Trailing blank lines are ignored.
+//
+//   #  wall
+//   .  floor
+//   +  closed door
+//   "  tall grass
+//   ~  smoke
+//   =  glass wall
+//   *  wall with a torch
+//   o  floor with an orc
+//   g  floor with an orc standing guard
+//   a  floor with an orc lying in ambush
+//   c  floor with a cowardly orc
+//   T  floor with a troll
+//   A  floor with an archer
+//   !  floor with a health potion
+const LEGEND: &[(char, TerrainTile)] = &[
+    ('#', TerrainTile::Wall),
+    ('.', TerrainTile::Floor),
+    ('+', TerrainTile::Door),
+    ('"', TerrainTile::TallGrass),
+    ('~', TerrainTile::Smoke),
+    ('=', TerrainTile::GlassWall),
+    ('*', TerrainTile::Torch),
+    ('o', TerrainTile::Npc(NpcType::Orc)),
+    (
+        'g',
+        TerrainTile::NpcWithArchetype(NpcType::Orc, Archetype::Guard),
+    ),
+    (
+        'a',
+        TerrainTile::NpcWithArchetype(NpcType::Orc, Archetype::Ambusher),
+    ),
+    (
+        'c',
+        TerrainTile::NpcWithArchetype(NpcType::Orc, Archetype::Coward),
+    ),
+    ('T', TerrainTile::Npc(NpcType::Troll)),
+    ('A', TerrainTile::Npc(NpcType::Archer)),
+    ('!', TerrainTile::Item(ItemType::HealthPotion)),
+];
+
+const BUILT_IN_PREFABS: &[(&str, &str)] = &[
+    ("ambush_hall", include_str!("prefabs/ambush_hall.txt")),
+    ("glass_cage", include_str!("prefabs/glass_cage.txt")),
+    ("pillared_hall", include_str!("prefabs/pillared_hall.txt")),
+    ("smoky_shrine", include_str!("prefabs/smoky_shrine.txt")),
+    ("treasure_room", include_str!("prefabs/treasure_room.txt")),
+    ("troll_den", include_str!("prefabs/troll_den.txt")),
+];
+
+// Each level gets up to this many prefabs, depending on how much space the generator left
+const MAX_PREFABS_PER_LEVEL: usize = 2;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PrefabErrorKind {
+    Empty,
+    UnknownCharacter(char),
+    RowTooShort { expected_width: usize },
+    RowTooLong { expected_width: usize },
+}
+
+// Line and column numbers start at 1
+#[derive(Debug, PartialEq, Eq)]
+pub struct PrefabError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: PrefabErrorKind,
+}
+
+impl fmt::Display for PrefabError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}: ", self.line, self.column)?;
+        match self.kind {
+            PrefabErrorKind::Empty => write!(f, "prefab is empty"),
+            PrefabErrorKind::UnknownCharacter(ch) => {
+                write!(f, "unknown character {:?} (expected one of", ch)?;
+                for &(legend_ch, _) in LEGEND {
+                    write!(f, " {}", legend_ch)?;
+                }
+                write!(f, ")")
+            }
+            PrefabErrorKind::RowTooShort { expected_width } => write!(
+                f,
+                "row ends early (all rows must be {} characters wide)",
+                expected_width
+            ),
+            PrefabErrorKind::RowTooLong { expected_width } => write!(
+                f,
+                "row is too long (all rows must be {} characters wide)",
+                expected_width
+            ),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Prefab {
+    tiles: Grid<TerrainTile>,
+}
+
+impl Prefab {
+    pub fn parse(text: &str) -> Result<Self, PrefabError> {
+        let mut rows = text.lines().collect::<Vec<_>>();
+        while rows.last().is_some_and(|row| row.trim().is_empty()) {
+            rows.pop();
+        }
+        let width = match rows.first() {
+            Some(row) => row.chars().count(),
+            None => {
+                return Err(PrefabError {
+                    line: 1,
+                    column: 1,
+                    kind: PrefabErrorKind::Empty,
+                })
+            }
+        };
+        let mut tiles = Vec::with_capacity(width * rows.len());
+        for (line_index, row) in rows.iter().enumerate() {
+            let error = |column_index: usize, kind| PrefabError {
+                line: line_index + 1,
+                column: column_index + 1,
+                kind,
+            };
+            let mut num_chars = 0;
+            for (column_index, ch) in row.chars().enumerate() {
+                if column_index == width {
+                    return Err(error(
+                        column_index,
+                        PrefabErrorKind::RowTooLong {
+                            expected_width: width,
+                        },
+                    ));
+                }
+                let &(_, tile) = LEGEND
+                    .iter()
+                    .find(|&&(legend_ch, _)| legend_ch == ch)
+                    .ok_or_else(|| error(column_index, PrefabErrorKind::UnknownCharacter(ch)))?;
+                tiles.push(tile);
+                num_chars += 1;
+            }
+            if num_chars < width {
+                return Err(error(
+                    num_chars,
+                    PrefabErrorKind::RowTooShort {
+                        expected_width: width,
+                    },
+                ));
+            }
+        }
+        let size = Size::new(width as u32, rows.len() as u32);
+        let tiles = Grid::new_fn(size, |coord| {
+            tiles[coord.y as usize * width + coord.x as usize]
+        });
+        Ok(Self { tiles })
+    }
+
+    // The built in prefabs are parsed the first time they are needed
+    pub fn built_in() -> &'static [Self] {
+        static BUILT_IN: OnceLock<Vec<Prefab>> = OnceLock::new();
+        BUILT_IN.get_or_init(|| {
+            BUILT_IN_PREFABS
+                .iter()
+                .map(|(name, text)| {
+                    Self::parse(text).unwrap_or_else(|error| panic!("prefab {}: {}", name, error))
+                })
+                .collect()
+        })
+    }
+
+    pub fn size(&self) -> Size {
+        self.tiles.size()
+    }
+
+    // Turns the prefab a quarter turn clockwise
+    pub fn rotated(&self) -> Self {
+        let height = self.size().height() as i32;
+        let size = Size::new(self.size().height(), self.size().width());
+        Self {
+            tiles: Grid::new_fn(size, |coord| {
+                *self
+                    .tiles
+                    .get_checked(Coord::new(coord.y, height - 1 - coord.x))
+            }),
+        }
+    }
+
+    // Flips the prefab from left to right
+    pub fn mirrored(&self) -> Self {
+        let width = self.size().width() as i32;
+        Self {
+            tiles: Grid::new_fn(self.size(), |coord| {
+                *self
+                    .tiles
+                    .get_checked(Coord::new(width - 1 - coord.x, coord.y))
+            }),
+        }
+    }
+
+    // Returns the prefab in one of its 8 orientations, chosen at random
+    fn choose_orientation<R: Rng>(&self, rng: &mut R) -> Self {
+        let mut prefab = if rng.gen() {
+            self.mirrored()
+        } else {
+            self.clone()
+        };
+        for _ in 0..rng.gen_range(0..4) {
+            prefab = prefab.rotated();
+        }
+        prefab
+    }
+
+    // A prefab can be placed anywhere that it only covers walls, and doesn't touch the edge of
+    // the map, so it doesn't overwrite anything the generator placed
+    fn fits_at(&self, top_left: Coord, grid: &Grid<TerrainTile>) -> bool {
+        let bottom_right = top_left + self.size().to_coord().unwrap() - Coord::new(1, 1);
+        if top_left.x < 1
+            || top_left.y < 1
+            || bottom_right.x >= grid.size().width() as i32 - 1
+            || bottom_right.y >= grid.size().height() as i32 - 1
+        {
+            return false;
+        }
+        self.size()
+            .coord_iter_row_major()
+            .all(|offset| *grid.get_checked(top_left + offset) == TerrainTile::Wall)
+    }
+
+    pub fn stamp(&self, top_left: Coord, grid: &mut Grid<TerrainTile>) {
+        for (offset, &tile) in self.tiles.enumerate() {
+            *grid.get_checked_mut(top_left + offset) = tile;
+        }
+    }
+}
+
+// Parses a whole level for tests. It's written in the same way as a prefab, with the addition of
+// `@` for the player.
+#[cfg(test)]
+pub fn parse_level(rows: &[&str]) -> Grid<TerrainTile> {
+    let size = Size::new(rows[0].len() as u32, rows.len() as u32);
+    Grid::new_fn(size, |coord| {
+        let ch = rows[coord.y as usize].as_bytes()[coord.x as usize] as char;
+        if ch == '@' {
+            return TerrainTile::Player;
+        }
+        let &(_, tile) = LEGEND
+            .iter()
+            .find(|&&(legend_ch, _)| legend_ch == ch)
+            .unwrap_or_else(|| panic!("unknown character {:?}", ch));
+        tile
+    })
+}
+
+// The area of a level covered by a prefab
+pub struct StampedPrefab {
+    top_left: Coord,
+    size: Size,
+}
+
+impl StampedPrefab {
+    pub fn contains(&self, coord: Coord) -> bool {
+        coord.x >= self.top_left.x
+            && coord.y >= self.top_left.y
+            && coord.x < self.top_left.x + self.size.width() as i32
+            && coord.y < self.top_left.y + self.size.height() as i32
+    }
+
+    // Returns true for the cells around the outside of the prefab, apart from its corners
+    pub fn is_outer_wall(&self, coord: Coord) -> bool {
+        if !self.contains(coord) {
+            return false;
+        }
+        let bottom_right = self.top_left + self.size.to_coord().unwrap() - Coord::new(1, 1);
+        let on_vertical_side = coord.x == self.top_left.x || coord.x == bottom_right.x;
+        let on_horizontal_side = coord.y == self.top_left.y || coord.y == bottom_right.y;
+        on_vertical_side != on_horizontal_side
+    }
+}
+
+// Stamps randomly chosen and oriented prefabs into parts of the map which are solid wall,
+// returning where they were stamped. The prefabs aren't connected to the rest of the level,
+// which is left to the caller.
+pub fn stamp_prefabs<R: Rng>(
+    prefabs: &[Prefab],
+    grid: &mut Grid<TerrainTile>,
+    rng: &mut R,
+) -> Vec<StampedPrefab> {
+    let mut stamped = Vec::new();
+    for _ in 0..MAX_PREFABS_PER_LEVEL {
+        let prefab = match prefabs.choose(rng) {
+            Some(prefab) => prefab.choose_orientation(rng),
+            None => break,
+        };
+        let top_left = grid
+            .coord_iter()
+            .filter(|&coord| prefab.fits_at(coord, grid))
+            .choose(rng);
+        if let Some(top_left) = top_left {
+            prefab.stamp(top_left, grid);
+            stamped.push(StampedPrefab {
+                top_left,
+                size: prefab.size(),
+            });
+        }
+    }
+    stamped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_isaac::Isaac64Rng;
+
+    fn render(prefab: &Prefab) -> Vec<String> {
+        prefab
+            .tiles
+            .rows()
+            .map(|row| {
+                row.iter()
+                    .map(|&tile| {
+                        let &(ch, _) = LEGEND.iter().find(|&&(_, t)| t == tile).unwrap();
+                        ch
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn built_in_prefabs_are_valid() {
+        assert_eq!(Prefab::built_in().len(), BUILT_IN_PREFABS.len());
+    }
+
+    #[test]
+    fn errors_point_at_the_line_and_column() {
+        let parse_error = |text| Prefab::parse(text).err().unwrap();
+        assert_eq!(
+            parse_error("###\n#x#\n###\n"),
+            PrefabError {
+                line: 2,
+                column: 2,
+                kind: PrefabErrorKind::UnknownCharacter('x'),
+            }
+        );
+        assert_eq!(
+            parse_error("####\n#.\n####\n"),
+            PrefabError {
+                line: 2,
+                column: 3,
+                kind: PrefabErrorKind::RowTooShort { expected_width: 4 },
+            }
+        );
+        assert_eq!(
+            parse_error("###\n#.#\n####\n"),
+            PrefabError {
+                line: 3,
+                column: 4,
+                kind: PrefabErrorKind::RowTooLong { expected_width: 3 },
+            }
+        );
+        assert_eq!(parse_error("\n\n").kind, PrefabErrorKind::Empty);
+        assert_eq!(
+            parse_error("#?#").to_string(),
+            "line 1, column 2: unknown character '?' (expected one of # . + \" ~ = * o g a c T A !)"
+        );
+    }
+
+    #[test]
+    fn rotating_and_mirroring() {
+        let prefab = Prefab::parse("#.o\n!..\n").unwrap();
+        assert_eq!(render(&prefab.rotated()), ["!#", "..", ".o"]);
+        assert_eq!(render(&prefab.mirrored()), ["o.#", "..!"]);
+        assert_eq!(
+            render(&prefab.rotated().rotated().rotated().rotated()),
+            render(&prefab)
+        );
+    }
+
+    #[test]
+    fn prefabs_are_only_stamped_over_walls() {
+        let size = Size::new(30, 20);
+        let mut grid = Grid::new_copy(size, TerrainTile::Wall);
+        // Leave a block of solid wall in the bottom right corner, big enough for one prefab
+        for coord in size.coord_iter_row_major() {
+            if coord.x < 18 || coord.y < 10 {
+                *grid.get_checked_mut(coord) = TerrainTile::Floor;
+            }
+        }
+        let original = grid.clone();
+        let prefab = Prefab::parse("#####\n#.o.#\n#####\n").unwrap();
+        let mut rng = Isaac64Rng::seed_from_u64(0);
+        stamp_prefabs(&[prefab], &mut grid, &mut rng);
+        for (coord, &tile) in grid.enumerate() {
+            if tile != *original.get_checked(coord) {
+                assert!(*original.get_checked(coord) == TerrainTile::Wall);
+                assert!(coord.x >= 18 && coord.y >= 10);
+            }
+        }
+        assert!(grid
+            .iter()
+            .any(|&tile| tile == TerrainTile::Npc(NpcType::Orc)));
+    }
+}
@@ -0,0 +1,227 @@
+use super::{carve_corridor, choose_npc_type, Generator, SpawnTable, TerrainTile};
+use crate::world::ItemType;
+use grid_2d::{Coord, Grid, Size};
+use rand::{seq::IteratorRandom, seq::SliceRandom, Rng};
+
+// A rectangular area of the map
+pub struct Room {
+    pub top_left: Coord,
+    pub size: Size,
+}
+
+impl Room {
+    // Returns a randomly sized room at a random position within `bounds`
+    fn choose<R: Rng>(bounds: Size, rng: &mut R) -> Self {
+        let width = rng.gen_range(5..11);
+        let height = rng.gen_range(5..9);
+        let size = Size::new(width, height);
+        let top_left_bounds = bounds - size;
+        let left = rng.gen_range(0..top_left_bounds.width());
+        let top = rng.gen_range(0..top_left_bounds.height());
+        let top_left = Coord::new(left as i32, top as i32);
+        Self { top_left, size }
+    }
+
+    // Returns a coord at the centre of the room, rounding down
+    pub fn centre(&self) -> Coord {
+        self.top_left + self.size.to_coord().unwrap() / 2
+    }
+
+    // Returns an iterator over all the coordinates in the room in row major order
+    fn coords<'a>(&'a self) -> impl 'a + Iterator<Item = Coord> {
+        self.size
+            .coord_iter_row_major()
+            .map(move |coord| self.top_left + coord)
+    }
+
+    // Returns an iterator over the coordinates of the empty floor cells in the room
+    fn free_floor_coords<'a>(
+        &'a self,
+        grid: &'a Grid<Option<TerrainTile>>,
+    ) -> impl 'a + Iterator<Item = Coord> {
+        self.coords()
+            .filter(move |&coord| *grid.get_checked(coord) == Some(TerrainTile::Floor))
+    }
+
+    // Returns true if and only if each cell of `grid` overlapping this room is `None`
+    fn only_intersects_empty(&self, grid: &Grid<Option<TerrainTile>>) -> bool {
+        self.coords().all(|coord| grid.get_checked(coord).is_none())
+    }
+
+    // Updates `grid`, setting each cell overlapping this room to `Some(TerrainTile::Floor)`.
+    // The top and left sides of the room are set to `Some(TerrainTile::Wall)` instead.
+    // This prevents a pair of rooms being placed immediately adjacent to one another.
+    pub fn carve_out(&self, grid: &mut Grid<Option<TerrainTile>>) {
+        for coord in self.coords() {
+            let cell = grid.get_checked_mut(coord);
+            if coord.x == self.top_left.x || coord.y == self.top_left.y {
+                *cell = Some(TerrainTile::Wall);
+            } else {
+                *cell = Some(TerrainTile::Floor);
+            }
+        }
+    }
+
+    // Places a door in each gap in the walls around the room where a corridor enters it. Call
+    // this after carving corridors. Gaps wider than one cell are left open, as are gaps right
+    // next to a door in another room's wall, so there is only one door between adjacent rooms.
+    pub fn place_doors(&self, grid: &mut Grid<Option<TerrainTile>>) {
+        let is_wall = |grid: &Grid<Option<TerrainTile>>, coord| {
+            matches!(grid.get(coord), Some(None) | Some(Some(TerrainTile::Wall)))
+        };
+        let is_door = |grid: &Grid<Option<TerrainTile>>, coord| {
+            grid.get(coord) == Some(&Some(TerrainTile::Door))
+        };
+        let bottom_right = self.top_left + self.size.to_coord().unwrap();
+        let horizontal_walls = (self.top_left.x + 1..bottom_right.x)
+            .flat_map(|x| {
+                vec![
+                    Coord::new(x, self.top_left.y),
+                    Coord::new(x, bottom_right.y),
+                ]
+            })
+            .map(|coord| (coord, Coord::new(1, 0), Coord::new(0, 1)));
+        let vertical_walls = (self.top_left.y + 1..bottom_right.y)
+            .flat_map(|y| {
+                vec![
+                    Coord::new(self.top_left.x, y),
+                    Coord::new(bottom_right.x, y),
+                ]
+            })
+            .map(|coord| (coord, Coord::new(0, 1), Coord::new(1, 0)));
+        for (coord, along_wall, through_wall) in horizontal_walls.chain(vertical_walls) {
+            if grid.get(coord) == Some(&Some(TerrainTile::Floor))
+                && is_wall(grid, coord - along_wall)
+                && is_wall(grid, coord + along_wall)
+                && !is_door(grid, coord - through_wall)
+                && !is_door(grid, coord + through_wall)
+            {
+                *grid.get_checked_mut(coord) = Some(TerrainTile::Door);
+            }
+        }
+    }
+
+    // Adds npcs and health potions to the room, in numbers chosen from `spawn_table`
+    pub fn populate<R: Rng>(
+        &self,
+        spawn_table: &SpawnTable,
+        grid: &mut Grid<Option<TerrainTile>>,
+        rng: &mut R,
+    ) {
+        let &num_npcs = spawn_table.npcs_per_room_distribution.choose(rng).unwrap();
+        self.place_npcs(num_npcs, spawn_table, grid, rng);
+        let &num_health_potions = spawn_table
+            .health_potions_per_room_distribution
+            .choose(rng)
+            .unwrap();
+        self.place_health_potions(num_health_potions, grid, rng);
+    }
+
+    // Place `n` randomly chosen NPCs at random positions within the room
+    fn place_npcs<R: Rng>(
+        &self,
+        n: usize,
+        spawn_table: &SpawnTable,
+        grid: &mut Grid<Option<TerrainTile>>,
+        rng: &mut R,
+    ) {
+        for coord in self
+            .coords()
+            .filter(|&coord| grid.get_checked(coord).unwrap() == TerrainTile::Floor)
+            .choose_multiple(rng, n)
+        {
+            let npc_type = choose_npc_type(spawn_table, rng);
+            *grid.get_checked_mut(coord) = Some(TerrainTile::Npc(npc_type));
+        }
+    }
+
+    fn place_health_potions<R: Rng>(
+        &self,
+        n: usize,
+        grid: &mut Grid<Option<TerrainTile>>,
+        rng: &mut R,
+    ) {
+        for coord in self
+            .coords()
+            .filter(|&coord| grid.get_checked(coord).unwrap() == TerrainTile::Floor)
+            .choose_multiple(rng, n)
+        {
+            *grid.get_checked_mut(coord) = Some(TerrainTile::Item(ItemType::HealthPotion));
+        }
+    }
+}
+
+// Places rooms of random sizes at random positions wherever they don't overlap an existing room,
+// then connects each room to the one placed before it with a corridor
+// Adds stairs to the next level on the empty floor closest to the centre of the last room. If the
+// player's room is the only one (or the last room is full), they go in the player's room instead,
+// as far from the player as possible.
+pub fn place_stairs(rooms: &[Room], grid: &mut Grid<Option<TerrainTile>>) {
+    let stairs_coord = rooms
+        .last()
+        .filter(|_| rooms.len() > 1)
+        .and_then(|room| {
+            room.free_floor_coords(grid)
+                .min_by_key(|coord| coord.distance2(room.centre()))
+        })
+        .or_else(|| {
+            let room = rooms.first()?;
+            room.free_floor_coords(grid)
+                .max_by_key(|coord| coord.distance2(room.centre()))
+        });
+    if let Some(stairs_coord) = stairs_coord {
+        *grid.get_checked_mut(stairs_coord) = Some(TerrainTile::Stairs);
+    }
+}
+
+pub struct Rooms;
+
+impl Generator for Rooms {
+    fn generate<R: Rng>(
+        &self,
+        size: Size,
+        spawn_table: &SpawnTable,
+        rng: &mut R,
+    ) -> Grid<TerrainTile> {
+        let mut grid = Grid::new_copy(size, None);
+        let mut rooms = Vec::new();
+        let mut room_centres = Vec::new();
+
+        // Attempt to add a room a constant number of times
+        const NUM_ATTEMPTS: usize = 100;
+        for _ in 0..NUM_ATTEMPTS {
+            // Make a random room
+            let room = Room::choose(size, rng);
+
+            // Carve out the room unless it overlaps with an existing room
+            if room.only_intersects_empty(&grid) {
+                room.carve_out(&mut grid);
+
+                let room_centre = room.centre();
+
+                // Add the player to the centre of the room if it's the first room
+                if room_centres.is_empty() {
+                    *grid.get_checked_mut(room_centre) = Some(TerrainTile::Player);
+                }
+
+                // Build up a list of all room centres for use in constructing corridors
+                room_centres.push(room_centre);
+
+                room.populate(spawn_table, &mut grid, rng);
+                rooms.push(room);
+            }
+        }
+
+        place_stairs(&rooms, &mut grid);
+
+        // Add corridors connecting every adjacent pair of room centres
+        for window in room_centres.windows(2) {
+            carve_corridor(window[0], window[1], &mut grid);
+        }
+        for room in rooms.iter() {
+            room.place_doors(&mut grid);
+        }
+
+        grid.map(|t| t.unwrap_or(TerrainTile::Wall))
+    }
+}
@@ -1,10 +1,10 @@
-use crate::app::colours;
+use crate::app::{colours, InventoryAction};
 use crate::game::LogMessage;
-use crate::world::HitPoints;
+use crate::world::{Experience, HitPoints, ItemType, LevelUpChoice};
 use chargrid::{
     decorator::{AlignView, Alignment, BoundView},
     render::{ColModify, Frame, Style, View, ViewCell, ViewContext},
-    text::{RichTextPartOwned, RichStringViewSingleLine, StringViewSingleLine},
+    text::{RichTextPartOwned, StringViewSingleLine},
 };
 use coord_2d::{Coord, Size};
 use rgb24::Rgb24;
@@ -13,6 +13,8 @@ use chargrid::text::RichTextViewSingleLine;
 const HEALTH_WIDTH: u32 = 10;
 const HEALTH_FILL_COLOUR: Rgb24 = Rgb24::new(200, 0, 0);
 const HEALTH_EMPTY_COLOUR: Rgb24 = Rgb24::new(100, 0, 0);
+const EXPERIENCE_FILL_COLOUR: Rgb24 = Rgb24::new(0, 150, 200);
+const EXPERIENCE_EMPTY_COLOUR: Rgb24 = Rgb24::new(0, 50, 100);
 
 #[derive(Default)]
 struct HealthView {
@@ -41,22 +43,79 @@ impl View<HitPoints> for HealthView {
         if hit_points.current > 0 {
             health_fill_width = health_fill_width.max(1);
         }
-        for i in 0..health_fill_width {
-            frame.set_cell_relative(
-                Coord::new(i as i32, 0),
-                0,
-                ViewCell::new().with_background(HEALTH_FILL_COLOUR),
-                context,
-            );
-        }
-        for i in health_fill_width..HEALTH_WIDTH {
-            frame.set_cell_relative(
-                Coord::new(i as i32, 0),
-                0,
-                ViewCell::new().with_background(HEALTH_EMPTY_COLOUR),
-                context,
-            );
-        }
+        render_bar(
+            health_fill_width,
+            HEALTH_FILL_COLOUR,
+            HEALTH_EMPTY_COLOUR,
+            context,
+            frame,
+        );
+    }
+}
+
+#[derive(Default)]
+struct ExperienceView {
+    buf: String,
+}
+
+impl View<Experience> for ExperienceView {
+    fn view<F: Frame, C: ColModify>(
+        &mut self,
+        experience: Experience,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) {
+        use std::fmt::Write;
+        self.buf.clear();
+        let xp_to_next_level = experience.xp_to_next_level();
+        write!(
+            &mut self.buf,
+            "L{} {}/{}",
+            experience.level, experience.xp, xp_to_next_level
+        )
+        .unwrap();
+        let mut experience_text_view = BoundView {
+            size: Size::new(HEALTH_WIDTH, 1),
+            view: AlignView {
+                alignment: Alignment::centre(),
+                view: StringViewSingleLine::new(Style::new().with_foreground(Rgb24::new_grey(255))),
+            },
+        };
+        experience_text_view.view(&self.buf, context.add_depth(1), frame);
+        let experience_fill_width = (experience.xp * HEALTH_WIDTH) / xp_to_next_level;
+        render_bar(
+            experience_fill_width,
+            EXPERIENCE_FILL_COLOUR,
+            EXPERIENCE_EMPTY_COLOUR,
+            context,
+            frame,
+        );
+    }
+}
+
+// Draws a horizontal bar `HEALTH_WIDTH` cells wide, the first `fill_width` of which are filled
+fn render_bar<F: Frame, C: ColModify>(
+    fill_width: u32,
+    fill_colour: Rgb24,
+    empty_colour: Rgb24,
+    context: ViewContext<C>,
+    frame: &mut F,
+) {
+    for i in 0..fill_width {
+        frame.set_cell_relative(
+            Coord::new(i as i32, 0),
+            0,
+            ViewCell::new().with_background(fill_colour),
+            context,
+        );
+    }
+    for i in fill_width..HEALTH_WIDTH {
+        frame.set_cell_relative(
+            Coord::new(i as i32, 0),
+            0,
+            ViewCell::new().with_background(empty_colour),
+            context,
+        );
     }
 }
 
@@ -90,38 +149,104 @@ impl <'a> View<&'a [LogMessage]> for MessagesView {
             buf[1].style.bold = Some(true);
             buf[2].style.foreground = Some(Rgb24::new_grey(255));
             match message {
-                PlayerAttacksNpc(npc_type) => {
-                    write!(&mut buf[0].text, "You attack the ").unwrap();
+                PlayerAttacksNpc(npc_type, damage) => {
+                    write!(&mut buf[0].text, "You hit the ").unwrap();
                     write!(&mut buf[1].text, "{}", npc_type.name()).unwrap();
                     buf[1].style.foreground = Some(colours::npc_colour(npc_type));
-                    write!(&mut buf[2].text, ".").unwrap();
+                    if damage == 0 {
+                        write!(&mut buf[2].text, " but do no damage.").unwrap();
+                    } else {
+                        write!(&mut buf[2].text, " for {} damage.", damage).unwrap();
+                    }
                 }
-                NpcAttacksPlayer(npc_type) => {
+                NpcAttacksPlayer(npc_type, damage) => {
                     write!(&mut buf[0].text, "The ").unwrap();
                     write!(&mut buf[1].text, "{}", npc_type.name()).unwrap();
                     buf[1].style.foreground = Some(colours::npc_colour(npc_type));
-                    write!(&mut buf[2].text, " attacks you.").unwrap();
+                    if damage == 0 {
+                        write!(&mut buf[2].text, " hits you but does no damage.").unwrap();
+                    } else {
+                        write!(&mut buf[2].text, " hits you for {} damage.", damage).unwrap();
+                    }
                 }
-                PlayerKillsNpc(npc_type) => {
-                    write!(&mut buf[0].text, "You killed the ").unwrap();
+                NpcShootsPlayer(npc_type, damage) => {
+                    write!(&mut buf[0].text, "The ").unwrap();
                     write!(&mut buf[1].text, "{}", npc_type.name()).unwrap();
                     buf[1].style.foreground = Some(colours::npc_colour(npc_type));
-                    write!(&mut buf[2].text, ".").unwrap();
+                    if damage == 0 {
+                        write!(&mut buf[2].text, "'s arrow bounces off you.").unwrap();
+                    } else {
+                        write!(&mut buf[2].text, " shoots you for {} damage.", damage).unwrap();
+                    }
                 }
-                NpcKillsPlayer(npc_type) => {
+                PlayerKillsNpc(npc_type, damage) => {
+                    write!(&mut buf[0].text, "You hit the ").unwrap();
+                    write!(&mut buf[1].text, "{}", npc_type.name()).unwrap();
+                    buf[1].style.foreground = Some(colours::npc_colour(npc_type));
+                    write!(&mut buf[2].text, " for {} damage, killing it.", damage).unwrap();
+                }
+                NpcKillsPlayer(npc_type, damage) => {
                     write!(&mut buf[0].text, "THE ").unwrap();
                     buf[0].style.foreground = Some(Rgb24::new(255, 0, 0));
                     write!(&mut buf[1].text, "{}", npc_type.name()).unwrap();
                     buf[1].text.make_ascii_uppercase();
                     buf[1].style.foreground = Some(colours::npc_colour(npc_type));
-                    write!(&mut buf[2].text, " KILLS YOU!").unwrap();
+                    write!(&mut buf[2].text, " KILLS YOU! ({} DAMAGE)", damage).unwrap();
                     buf[2].style.foreground = Some(Rgb24::new(255, 0, 0));
                 }
+                PlayerGets(item_type) => {
+                    write!(&mut buf[0].text, "You get the ").unwrap();
+                    write!(&mut buf[1].text, "{}", item_type.name()).unwrap();
+                    buf[1].style.foreground = Some(colours::item_colour(item_type));
+                    write!(&mut buf[2].text, ".").unwrap();
+                }
+                PlayerInventoryIsFull => {
+                    write!(&mut buf[0].text, "Inventory is full!").unwrap();
+                }
+                NoItemUnderPlayer => {
+                    write!(&mut buf[0].text, "Nothing to get!").unwrap();
+                }
+                PlayerHeals(amount) => {
+                    write!(&mut buf[0].text, "You heal ").unwrap();
+                    write!(&mut buf[1].text, "{}", amount).unwrap();
+                    buf[1].style.foreground = Some(colours::HEALTH_POTION);
+                    write!(&mut buf[2].text, " hit points.").unwrap();
+                }
+                PlayerIsAtFullHealth => {
+                    write!(&mut buf[0].text, "You are already at full health.").unwrap();
+                }
+                PlayerDrops(item_type) => {
+                    write!(&mut buf[0].text, "You drop the ").unwrap();
+                    write!(&mut buf[1].text, "{}", item_type.name()).unwrap();
+                    buf[1].style.foreground = Some(colours::item_colour(item_type));
+                    write!(&mut buf[2].text, ".").unwrap();
+                }
+                NoSpaceToDropItem => {
+                    write!(&mut buf[0].text, "No space to drop item!").unwrap();
+                }
+                PlayerDescends(dungeon_level) => {
+                    write!(&mut buf[0].text, "You descend to depth ").unwrap();
+                    write!(&mut buf[1].text, "{}", dungeon_level).unwrap();
+                    buf[1].style.foreground = Some(colours::STAIRS);
+                    write!(&mut buf[2].text, ".").unwrap();
+                }
+                NoStairsUnderPlayer => {
+                    write!(&mut buf[0].text, "There are no stairs here!").unwrap();
+                }
+                PlayerLevelsUp(level) => {
+                    write!(&mut buf[0].text, "You reach level ").unwrap();
+                    write!(&mut buf[1].text, "{}", level).unwrap();
+                    buf[1].style.foreground = Some(EXPERIENCE_FILL_COLOUR);
+                    write!(&mut buf[2].text, "!").unwrap();
+                }
+                PlayerOpensDoor => {
+                    write!(&mut buf[0].text, "You open the door.").unwrap();
+                }
             }
         }
-        const NUM_MESSAGES: usize = 4;
+        const NUM_MESSAGES: usize = 3;
         let start_index = messages.len().saturating_sub(NUM_MESSAGES);
-        for (i, &message) in (&messages[start_index..]).iter().enumerate() {
+        for (i, &message) in messages[start_index..].iter().enumerate() {
             format_message(&mut self.buf, message);
             let offset = Coord::new(0, i as i32);
             RichTextViewSingleLine.view(
@@ -136,13 +261,17 @@ impl <'a> View<&'a [LogMessage]> for MessagesView {
 
 pub struct UiData<'a> {
     pub player_hit_points: HitPoints,
+    pub player_experience: Experience,
+    pub dungeon_level: u32,
     pub messages: &'a [LogMessage],
 }
 
 #[derive(Default)]
 pub struct UiView {
     health_view: HealthView,
+    experience_view: ExperienceView,
     messages_view: MessagesView,
+    buf: String,
 }
 
 impl<'a> View<UiData<'a>> for UiView {
@@ -152,10 +281,134 @@ impl<'a> View<UiData<'a>> for UiView {
         context: ViewContext<C>,
         frame: &mut F,
     ) {
+        use std::fmt::Write;
+        // The top row shows the player's health, experience and depth side by side, leaving the
+        // full width of the rows below for messages
+        let column_width = HEALTH_WIDTH as i32 + 1;
         self.health_view
             .view(data.player_hit_points, context, frame);
-        let message_log_offset = Coord::new(HEALTH_WIDTH as i32 + 1, 0);
+        self.experience_view.view(
+            data.player_experience,
+            context.add_offset(Coord::new(column_width, 0)),
+            frame,
+        );
+        self.buf.clear();
+        write!(&mut self.buf, "Depth: {}", data.dungeon_level).unwrap();
+        StringViewSingleLine::new(Style::new().with_foreground(Rgb24::new_grey(255))).view(
+            &self.buf,
+            context.add_offset(Coord::new(column_width * 2, 0)),
+            frame,
+        );
+        let message_log_offset = Coord::new(0, 1);
         self.messages_view
             .view(data.messages, context.add_offset(message_log_offset), frame);
     }
 }
+
+pub struct InventoryData<'a> {
+    pub action: InventoryAction,
+    pub item_types: &'a [Option<ItemType>],
+}
+
+pub struct InventoryView {
+    buf: Vec<RichTextPartOwned>,
+}
+
+impl Default for InventoryView {
+    fn default() -> Self {
+        let common = RichTextPartOwned::new(String::new(), Style::new());
+        Self {
+            buf: vec![common.clone(), common],
+        }
+    }
+}
+
+impl<'a> View<InventoryData<'a>> for InventoryView {
+    fn view<F: Frame, C: ColModify>(
+        &mut self,
+        data: InventoryData,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) {
+        let title = match data.action {
+            InventoryAction::Use => "Use which item? (escape to cancel)",
+            InventoryAction::Drop => "Drop which item? (escape to cancel)",
+        };
+        StringViewSingleLine::new(
+            Style::new()
+                .with_foreground(Rgb24::new_grey(255))
+                .with_bold(true),
+        )
+        .view(title, context, frame);
+        for (i, &item_type) in data.item_types.iter().enumerate() {
+            use std::fmt::Write;
+            let hotkey = (b'a' + i as u8) as char;
+            self.buf[0].text.clear();
+            self.buf[1].text.clear();
+            self.buf[0].style.foreground = Some(Rgb24::new_grey(255));
+            write!(&mut self.buf[0].text, "{}) ", hotkey).unwrap();
+            match item_type {
+                Some(item_type) => {
+                    write!(&mut self.buf[1].text, "{}", item_type.name()).unwrap();
+                    self.buf[1].style.foreground = Some(colours::item_colour(item_type));
+                }
+                None => {
+                    write!(&mut self.buf[1].text, "-").unwrap();
+                    self.buf[1].style.foreground = Some(Rgb24::new_grey(127));
+                }
+            }
+            let offset = Coord::new(0, i as i32 + 2);
+            RichTextViewSingleLine.view(
+                self.buf.iter().map(|part| part.as_rich_text_part()),
+                context.add_offset(offset),
+                frame,
+            );
+        }
+    }
+}
+
+pub struct LevelUpView {
+    buf: Vec<RichTextPartOwned>,
+}
+
+impl Default for LevelUpView {
+    fn default() -> Self {
+        let common = RichTextPartOwned::new(String::new(), Style::new());
+        Self {
+            buf: vec![common.clone(), common],
+        }
+    }
+}
+
+impl View<()> for LevelUpView {
+    fn view<F: Frame, C: ColModify>(&mut self, (): (), context: ViewContext<C>, frame: &mut F) {
+        StringViewSingleLine::new(
+            Style::new()
+                .with_foreground(Rgb24::new_grey(255))
+                .with_bold(true),
+        )
+        .view("You level up! Choose an improvement:", context, frame);
+        for (i, &choice) in LevelUpChoice::ALL.iter().enumerate() {
+            use std::fmt::Write;
+            let hotkey = (b'a' + i as u8) as char;
+            self.buf[0].text.clear();
+            self.buf[1].text.clear();
+            self.buf[0].style.foreground = Some(Rgb24::new_grey(255));
+            self.buf[1].style.foreground = Some(EXPERIENCE_FILL_COLOUR);
+            write!(&mut self.buf[0].text, "{}) ", hotkey).unwrap();
+            match choice {
+                LevelUpChoice::MaxHitPoints => {
+                    write!(&mut self.buf[1].text, "more max hit points").unwrap()
+                }
+                LevelUpChoice::Attack => write!(&mut self.buf[1].text, "more attack").unwrap(),
+                LevelUpChoice::Defence => write!(&mut self.buf[1].text, "more defence").unwrap(),
+            }
+            let offset = Coord::new(0, i as i32 + 2);
+            RichTextViewSingleLine.view(
+                self.buf.iter().map(|part| part.as_rich_text_part()),
+                context.add_offset(offset),
+                frame,
+            );
+        }
+    }
+}
@@ -1,6 +1,8 @@
-use crate::world::World;
+use crate::world::{Layer, Tile, World};
 use coord_2d::{Coord, Size};
 use grid_2d::Grid;
+use rgb24::Rgb24;
+use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Copy, Debug)]
 pub enum VisibilityAlgorithm {
@@ -8,8 +10,10 @@ pub enum VisibilityAlgorithm {
     Omniscient,
 }
 
-const VISION_DISTANCE_SQUARED: u32 = 100;
-const VISION_DISTANCE: shadowcast::vision_distance::Circle =
+// The player can see this far, but only cells which are lit are visible. Npcs can see just as
+// far, whether or not the cells are lit.
+const VISION_DISTANCE_SQUARED: u32 = 400;
+pub(crate) const VISION_DISTANCE: shadowcast::vision_distance::Circle =
     shadowcast::vision_distance::Circle::new_squared(VISION_DISTANCE_SQUARED);
 
 struct Visibility;
@@ -25,22 +29,58 @@ impl shadowcast::InputGrid for Visibility {
     }
 }
 
+// What the player saw in a cell the last time it was visible. Characters aren't remembered, as
+// they are unlikely to still be there.
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+struct RememberedCell {
+    floor: Option<Tile>,
+    feature: Option<Tile>,
+    object: Option<Tile>,
+}
+
+impl RememberedCell {
+    fn observe(coord: Coord, world: &World) -> Self {
+        Self {
+            floor: world.tile_at(coord, Layer::Floor),
+            feature: world.tile_at(coord, Layer::Feature),
+            object: world.tile_at(coord, Layer::Object),
+        }
+    }
+
+    fn tiles(self) -> impl Iterator<Item = (Layer, Tile)> {
+        let floor = self.floor.map(|tile| (Layer::Floor, tile));
+        let feature = self.feature.map(|tile| (Layer::Feature, tile));
+        let object = self.object.map(|tile| (Layer::Object, tile));
+        floor.into_iter().chain(feature).chain(object)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
 struct VisibilityCell {
     last_seen: u64,
+    // The total light reaching the cell from every light source, as of the last update
+    light: Rgb24,
+    remembered: RememberedCell,
 }
 
 impl Default for VisibilityCell {
     fn default() -> Self {
-        Self { last_seen: 0 }
+        Self {
+            last_seen: 0,
+            light: Rgb24::new_grey(0),
+            remembered: RememberedCell::default(),
+        }
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum CellVisibility {
     Currently,
     Previously,
     Never,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct VisibilityGrid {
     grid: Grid<VisibilityCell>,
     count: u64,
@@ -66,6 +106,54 @@ impl VisibilityGrid {
             CellVisibility::Never
         }
     }
+    // The tiles the player remembers in each cell which was visible before but isn't now, as
+    // they were when the player last saw them
+    pub fn remembered_tiles<'a>(&'a self) -> impl 'a + Iterator<Item = (Coord, Layer, Tile)> {
+        let count = self.count;
+        self.grid
+            .enumerate()
+            .filter(move |(_, cell)| cell.last_seen != 0 && cell.last_seen != count)
+            .flat_map(|(coord, cell)| {
+                cell.remembered
+                    .tiles()
+                    .map(move |(layer, tile)| (coord, layer, tile))
+            })
+    }
+    pub fn cell_light(&self, coord: Coord) -> Rgb24 {
+        self.grid
+            .get(coord)
+            .map(|cell| cell.light)
+            .unwrap_or(Rgb24::new_grey(0))
+    }
+    // Light spreads from each light source in the same way as the player's vision, so it is
+    // dimmed by partially opaque cells. It also fades with distance, reaching zero just past
+    // the light's radius.
+    fn update_light(&mut self, world: &World, shadowcast_context: &mut shadowcast::Context<u8>) {
+        for cell in self.grid.iter_mut() {
+            cell.light = Rgb24::new_grey(0);
+        }
+        for (light_coord, light) in world.lights() {
+            let radius_squared = light.radius * light.radius;
+            let grid = &mut self.grid;
+            shadowcast_context.for_each_visible(
+                light_coord,
+                &Visibility,
+                world,
+                shadowcast::vision_distance::Circle::new_squared(radius_squared),
+                255,
+                |coord, _visible_directions, visibility| {
+                    let distance_squared = coord.distance2(light_coord);
+                    let brightness = (radius_squared + 1 - distance_squared) * visibility as u32;
+                    let cell = grid.get_checked_mut(coord);
+                    cell.light = cell.light.saturating_add(
+                        light
+                            .colour
+                            .saturating_scalar_mul_div(brightness, (radius_squared + 1) * 255),
+                    );
+                },
+            );
+        }
+    }
     pub fn update(
         &mut self,
         player_coord: Coord,
@@ -74,10 +162,12 @@ impl VisibilityGrid {
         algorithm: VisibilityAlgorithm,
     ) {
         self.count += 1;
+        self.update_light(world, shadowcast_context);
         match algorithm {
             VisibilityAlgorithm::Omniscient => {
-                for cell in self.grid.iter_mut() {
+                for (coord, cell) in self.grid.enumerate_mut() {
                     cell.last_seen = self.count;
+                    cell.remembered = RememberedCell::observe(coord, world);
                 }
             }
             VisibilityAlgorithm::Shadowcast => {
@@ -91,10 +181,86 @@ impl VisibilityGrid {
                     255,
                     |coord, _visible_directions, _visibility| {
                         let cell = grid.get_checked_mut(coord);
-                        cell.last_seen = count;
+                        if cell.light.max_channel() > 0 {
+                            cell.last_seen = count;
+                            cell.remembered = RememberedCell::observe(coord, world);
+                        }
                     },
                 );
             }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::world::ItemType;
+
+    #[test]
+    fn only_lit_cells_in_line_of_sight_are_visible() {
+        // A long corridor with a torch at the far end, beyond the reach of the player's lantern
+        let (world, _) = World::from_rows(&[
+            "##################*######",
+            ".@.......................",
+            "#########################",
+        ]);
+        let mut visibility_grid = VisibilityGrid::new(world.size());
+        visibility_grid.update(
+            Coord::new(1, 1),
+            &world,
+            &mut shadowcast::Context::default(),
+            VisibilityAlgorithm::Shadowcast,
+        );
+        let visibility = |x| visibility_grid.cell_visibility(Coord::new(x, 1));
+        assert_eq!(visibility(5), CellVisibility::Currently);
+        assert_eq!(visibility(10), CellVisibility::Never);
+        assert_eq!(visibility(18), CellVisibility::Currently);
+        let torch_light = visibility_grid.cell_light(Coord::new(18, 1));
+        assert!(torch_light.r > torch_light.b);
+    }
+
+    #[test]
+    fn remembered_cells_only_change_when_seen_again() {
+        let (mut world, populate) = World::from_rows(&[
+            "####################",
+            ".@..!...............",
+            "####################",
+        ]);
+        let player = populate.player_entity;
+        let potion_coord = Coord::new(4, 1);
+        let potion = world
+            .spatial_table
+            .layers_at_checked(potion_coord)
+            .object
+            .unwrap();
+        let mut shadowcast_context = shadowcast::Context::default();
+        let mut visibility_grid = VisibilityGrid::new(world.size());
+        let mut move_player_and_update = |world: &mut World, coord| {
+            world.spatial_table.update_coord(player, coord).unwrap();
+            visibility_grid.update(
+                coord,
+                world,
+                &mut shadowcast_context,
+                VisibilityAlgorithm::Shadowcast,
+            );
+            visibility_grid
+                .remembered_tiles()
+                .filter(|&(coord, _, _)| coord == potion_coord)
+                .map(|(_, _, tile)| tile)
+                .collect::<Vec<_>>()
+        };
+        move_player_and_update(&mut world, Coord::new(1, 1));
+        // The potion is taken while the player is too far away to see it, but they still
+        // remember it being there
+        world.remove_entity(potion);
+        let remembered = move_player_and_update(&mut world, Coord::new(18, 1));
+        assert!(matches!(
+            remembered[..],
+            [Tile::Floor, Tile::Item(ItemType::HealthPotion)]
+        ));
+        move_player_and_update(&mut world, Coord::new(1, 1));
+        let remembered = move_player_and_update(&mut world, Coord::new(18, 1));
+        assert!(matches!(remembered[..], [Tile::Floor]));
+    }
+}
@@ -1,12 +1,17 @@
-use crate::behaviour::Agent;
-use crate::terrain::{self, TerrainTile};
+use crate::behaviour::{Agent, Archetype};
 use crate::game::LogMessage;
+use crate::terrain::{self, GeneratorKind, SpawnTable, TerrainTile};
 use coord_2d::{Coord, Size};
 use direction::CardinalDirection;
 use entity_table::{ComponentTable, Entity, EntityAllocator};
+use grid_2d::Grid;
+use line_2d::LineSegment;
 use rand::Rng;
+use rgb24::Rgb24;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct HitPoints {
     pub current: u32,
     pub max: u32,
@@ -18,12 +23,132 @@ impl HitPoints {
     }
 }
 
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Attack(pub u32);
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Defence(pub u32);
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Experience {
+    pub level: u32,
+    pub xp: u32,
+    unspent_level_ups: u32,
+}
+
+impl Experience {
+    fn new() -> Self {
+        Self {
+            level: 1,
+            xp: 0,
+            unspent_level_ups: 0,
+        }
+    }
+
+    pub fn xp_to_next_level(&self) -> u32 {
+        self.level * 20
+    }
+
+    // Adds xp, returning the number of levels gained
+    fn gain(&mut self, xp: u32) -> u32 {
+        self.xp += xp;
+        let mut levels_gained = 0;
+        while self.xp >= self.xp_to_next_level() {
+            self.xp -= self.xp_to_next_level();
+            self.level += 1;
+            levels_gained += 1;
+        }
+        self.unspent_level_ups += levels_gained;
+        levels_gained
+    }
+}
+
+// Lights the cells around an entity which emits it, fading out towards the edge of its radius
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Light {
+    pub colour: Rgb24,
+    pub radius: u32,
+}
+
+const LANTERN_LIGHT: Light = Light {
+    colour: Rgb24::new(255, 239, 207),
+    radius: 7,
+};
+
+const TORCH_LIGHT: Light = Light {
+    colour: Rgb24::new(255, 159, 63),
+    radius: 6,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LevelUpChoice {
+    MaxHitPoints,
+    Attack,
+    Defence,
+}
+
+impl LevelUpChoice {
+    pub const ALL: [Self; 3] = [Self::MaxHitPoints, Self::Attack, Self::Defence];
+}
+
 struct VictimDies;
 
+// Each tick of the game clock, every character gains energy equal to its speed. A character can
+// act once it has at least ENERGY_TO_ACT energy, and acting spends the energy cost of its action,
+// so a character twice as fast as another acts twice as often.
+pub const ENERGY_TO_ACT: i32 = 100;
+const NORMAL_SPEED: u32 = 10;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Speed(pub u32);
+
+// Can be negative after an expensive action, in which case the character must wait longer than
+// usual before acting again
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Energy(pub i32);
+
+// What a character did with its turn, which determines how much energy it spent
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Wait,
+    Move,
+    Attack,
+    OpenDoor,
+    GetItem,
+    UseItem,
+    DropItem,
+}
+
+impl Action {
+    pub fn energy_cost(self) -> i32 {
+        match self {
+            Self::Wait | Self::Move | Self::OpenDoor => 100,
+            Self::Attack => 120,
+            Self::GetItem | Self::UseItem | Self::DropItem => 50,
+        }
+    }
+}
+
+// The stats that a character is spawned with
+struct StatBlock {
+    hit_points: u32,
+    attack: u32,
+    defence: u32,
+    speed: u32,
+}
+
+const PLAYER_STATS: StatBlock = StatBlock {
+    hit_points: 20,
+    attack: 4,
+    defence: 1,
+    speed: NORMAL_SPEED,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum NpcType {
     Orc,
     Troll,
+    Archer,
 }
 
 impl NpcType {
@@ -31,11 +156,64 @@ impl NpcType {
         match self {
             Self::Orc => "orc",
             Self::Troll => "troll",
+            Self::Archer => "archer",
+        }
+    }
+
+    // How npcs of this type behave unless they are spawned with a different archetype
+    fn archetype(self) -> Archetype {
+        match self {
+            Self::Orc => Archetype::Wanderer,
+            Self::Troll => Archetype::Guard,
+            Self::Archer => Archetype::Wanderer,
+        }
+    }
+
+    fn can_open_doors(self) -> bool {
+        match self {
+            Self::Orc | Self::Archer => true,
+            Self::Troll => false,
+        }
+    }
+
+    fn experience_reward(self) -> u32 {
+        match self {
+            Self::Orc => 10,
+            Self::Troll => 25,
+            Self::Archer => 15,
+        }
+    }
+
+    fn stats(self) -> StatBlock {
+        match self {
+            Self::Orc => StatBlock {
+                hit_points: 4,
+                attack: 3,
+                defence: 0,
+                speed: NORMAL_SPEED,
+            },
+            Self::Troll => StatBlock {
+                hit_points: 10,
+                attack: 5,
+                defence: 2,
+                speed: 7,
+            },
+            Self::Archer => StatBlock {
+                hit_points: 3,
+                attack: 3,
+                defence: 0,
+                speed: NORMAL_SPEED,
+            },
         }
     }
+
+    // Npcs with a ranged attack shoot the player from a distance rather than walking up to them
+    pub fn has_ranged_attack(self) -> bool {
+        matches!(self, Self::Archer)
+    }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ItemType {
     HealthPotion,
 }
@@ -46,17 +224,101 @@ impl ItemType {
             Self::HealthPotion => "health potion",
         }
     }
+
+    // Items that glow light up their surroundings while they are lying on the ground
+    fn light(self) -> Option<Light> {
+        match self {
+            Self::HealthPotion => Some(Light {
+                colour: Rgb24::new(255, 63, 255),
+                radius: 2,
+            }),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Inventory {
+    slots: Vec<Option<Entity>>,
+}
+
+pub struct InventoryIsFull;
+
+#[derive(Debug)]
+pub struct InventorySlotIsEmpty;
+
+impl Inventory {
+    pub fn new(capacity: usize) -> Self {
+        let slots = vec![None; capacity];
+        Self { slots }
+    }
+
+    pub fn insert(&mut self, item: Entity) -> Result<(), InventoryIsFull> {
+        if let Some(slot) = self.slots.iter_mut().find(|s| s.is_none()) {
+            *slot = Some(item);
+            Ok(())
+        } else {
+            Err(InventoryIsFull)
+        }
+    }
+
+    pub fn slots(&self) -> &[Option<Entity>] {
+        &self.slots
+    }
+
+    pub fn get(&self, index: usize) -> Result<Entity, InventorySlotIsEmpty> {
+        self.slots
+            .get(index)
+            .cloned()
+            .flatten()
+            .ok_or(InventorySlotIsEmpty)
+    }
+
+    pub fn remove(&mut self, index: usize) -> Result<Entity, InventorySlotIsEmpty> {
+        self.slots
+            .get_mut(index)
+            .and_then(|slot| slot.take())
+            .ok_or(InventorySlotIsEmpty)
+    }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum Tile {
     Player,
     PlayerCorpse,
     Floor,
     Wall,
+    Stairs,
     Npc(NpcType),
     NpcCorpse(NpcType),
     Item(ItemType),
+    ClosedDoor,
+    OpenDoor,
+    TallGrass,
+    Smoke,
+    GlassWall,
+    Torch,
+}
+
+impl Tile {
+    // How much a tile obscures what is behind it. Opacities add up along a line of sight, which
+    // is blocked once they reach 255.
+    fn opacity(self) -> u8 {
+        match self {
+            Self::Wall | Self::ClosedDoor | Self::Torch => 255,
+            Self::Smoke => 127,
+            Self::TallGrass => 85,
+            _ => 0,
+        }
+    }
+
+    // Returns true for tiles that stop characters moving into their cell. Closed doors are solid
+    // but can be opened by bumping into them.
+    fn is_solid(self) -> bool {
+        matches!(
+            self,
+            Self::Wall | Self::ClosedDoor | Self::GlassWall | Self::Torch
+        )
+    }
 }
 
 entity_table::declare_entity_module! {
@@ -64,11 +326,18 @@ entity_table::declare_entity_module! {
         tile: Tile,
         npc_type: NpcType,
         hit_points: HitPoints,
+        attack: Attack,
+        defence: Defence,
+        experience: Experience,
         item: ItemType,
+        inventory: Inventory,
+        light: Light,
+        speed: Speed,
+        energy: Energy,
     }
 }
 
-use components::Components;
+use components::{Components, EntityData};
 
 spatial_table::declare_layers_module! {
     layers {
@@ -83,12 +352,19 @@ pub use layers::Layer;
 type SpatialTable = spatial_table::SpatialTable<layers::Layers>;
 pub type Location = spatial_table::Location<Layer>;
 
+#[derive(Serialize, Deserialize)]
 pub struct World {
     pub entity_allocator: EntityAllocator,
     pub components: Components,
     pub spatial_table: SpatialTable,
 }
 
+// The state of the player which is carried over between levels
+pub struct PlayerData {
+    entity_data: EntityData,
+    inventory_entity_data: Vec<Option<EntityData>>,
+}
+
 pub struct Populate {
     pub player_entity: Entity,
     pub ai_state: ComponentTable<Agent>,
@@ -106,7 +382,7 @@ impl World {
         }
     }
 
-    fn spawn_wall(&mut self, coord: Coord) {
+    fn spawn_feature(&mut self, coord: Coord, tile: Tile) -> Entity {
         let entity = self.entity_allocator.alloc();
         self.spatial_table
             .update(
@@ -117,7 +393,8 @@ impl World {
                 },
             )
             .unwrap();
-        self.components.tile.insert(entity, Tile::Wall);
+        self.components.tile.insert(entity, tile);
+        entity
     }
 
     fn spawn_floor(&mut self, coord: Coord) {
@@ -134,6 +411,20 @@ impl World {
         self.components.tile.insert(entity, Tile::Floor);
     }
 
+    fn spawn_stairs(&mut self, coord: Coord) {
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(
+                entity,
+                Location {
+                    coord,
+                    layer: Some(Layer::Floor),
+                },
+            )
+            .unwrap();
+        self.components.tile.insert(entity, Tile::Stairs);
+    }
+
     fn spawn_player(&mut self, coord: Coord) -> Entity {
         let entity = self.entity_allocator.alloc();
         self.spatial_table
@@ -146,9 +437,15 @@ impl World {
             )
             .unwrap();
         self.components.tile.insert(entity, Tile::Player);
+        self.insert_stats(entity, PLAYER_STATS);
+        // The player moves first, and npcs start gaining energy once they have
+        self.components.energy.insert(entity, Energy(ENERGY_TO_ACT));
+        self.components.experience.insert(entity, Experience::new());
+        const PLAYER_INVENTORY_CAPACITY: usize = 10;
         self.components
-            .hit_points
-            .insert(entity, HitPoints::new_full(20));
+            .inventory
+            .insert(entity, Inventory::new(PLAYER_INVENTORY_CAPACITY));
+        self.components.light.insert(entity, LANTERN_LIGHT);
         entity
     }
 
@@ -165,14 +462,22 @@ impl World {
             .unwrap();
         self.components.tile.insert(entity, Tile::Npc(npc_type));
         self.components.npc_type.insert(entity, npc_type);
-        let hit_points = match npc_type {
-            NpcType::Orc => HitPoints::new_full(2),
-            NpcType::Troll => HitPoints::new_full(6),
-        };
-        self.components.hit_points.insert(entity, hit_points);
+        self.insert_stats(entity, npc_type.stats());
         entity
     }
 
+    fn insert_stats(&mut self, entity: Entity, stats: StatBlock) {
+        self.components
+            .hit_points
+            .insert(entity, HitPoints::new_full(stats.hit_points));
+        self.components.attack.insert(entity, Attack(stats.attack));
+        self.components
+            .defence
+            .insert(entity, Defence(stats.defence));
+        self.components.speed.insert(entity, Speed(stats.speed));
+        self.components.energy.insert(entity, Energy(0));
+    }
+
     fn spawn_item(&mut self, coord: Coord, item_type: ItemType) {
         let entity = self.entity_allocator.alloc();
         self.spatial_table
@@ -186,10 +491,25 @@ impl World {
             .unwrap();
         self.components.tile.insert(entity, Tile::Item(item_type));
         self.components.item.insert(entity, item_type);
+        if let Some(light) = item_type.light() {
+            self.components.light.insert(entity, light);
+        }
+    }
+
+    pub fn populate<R: Rng>(
+        &mut self,
+        generator: GeneratorKind,
+        spawn_table: &SpawnTable,
+        rng: &mut R,
+    ) -> Populate {
+        let terrain =
+            terrain::generate_dungeon(self.spatial_table.grid_size(), &generator, spawn_table, rng);
+        self.spawn_terrain(&terrain)
     }
 
-    pub fn populate<R: Rng>(&mut self, rng: &mut R) -> Populate {
-        let terrain = terrain::generate_dungeon(self.spatial_table.grid_size(), rng);
+    // Spawns the entities described by each cell of `terrain`, which must be the same size as
+    // the world and contain exactly one player
+    pub fn spawn_terrain(&mut self, terrain: &Grid<TerrainTile>) -> Populate {
         let mut player_entity = None;
         let mut ai_state = ComponentTable::default();
         for (coord, &terrain_tile) in terrain.enumerate() {
@@ -199,14 +519,41 @@ impl World {
                     player_entity = Some(self.spawn_player(coord));
                 }
                 TerrainTile::Floor => self.spawn_floor(coord),
+                TerrainTile::Stairs => self.spawn_stairs(coord),
                 TerrainTile::Wall => {
                     self.spawn_floor(coord);
-                    self.spawn_wall(coord);
+                    self.spawn_feature(coord, Tile::Wall);
+                }
+                TerrainTile::Door => {
+                    self.spawn_floor(coord);
+                    self.spawn_feature(coord, Tile::ClosedDoor);
+                }
+                TerrainTile::TallGrass => {
+                    self.spawn_floor(coord);
+                    self.spawn_feature(coord, Tile::TallGrass);
+                }
+                TerrainTile::Smoke => {
+                    self.spawn_floor(coord);
+                    self.spawn_feature(coord, Tile::Smoke);
+                }
+                TerrainTile::GlassWall => {
+                    self.spawn_floor(coord);
+                    self.spawn_feature(coord, Tile::GlassWall);
+                }
+                TerrainTile::Torch => {
+                    self.spawn_floor(coord);
+                    let entity = self.spawn_feature(coord, Tile::Torch);
+                    self.components.light.insert(entity, TORCH_LIGHT);
                 }
                 TerrainTile::Npc(npc_type) => {
                     let entity = self.spawn_npc(coord, npc_type);
                     self.spawn_floor(coord);
-                    ai_state.insert(entity, Agent::new());
+                    ai_state.insert(entity, Agent::new(npc_type.archetype(), coord));
+                }
+                TerrainTile::NpcWithArchetype(npc_type, archetype) => {
+                    let entity = self.spawn_npc(coord, npc_type);
+                    self.spawn_floor(coord);
+                    ai_state.insert(entity, Agent::new(archetype, coord));
                 }
                 TerrainTile::Item(item_type) => {
                     self.spawn_item(coord, item_type);
@@ -221,12 +568,48 @@ impl World {
         }
     }
 
-    pub fn maybe_move_character(
+    pub fn clone_player_data(&self, player: Entity) -> PlayerData {
+        let entity_data = self.components.clone_entity_data(player);
+        let inventory_entity_data = entity_data
+            .inventory
+            .as_ref()
+            .expect("player has no inventory")
+            .slots()
+            .iter()
+            .map(|slot| slot.map(|item| self.components.clone_entity_data(item)))
+            .collect();
+        PlayerData {
+            entity_data,
+            inventory_entity_data,
+        }
+    }
+
+    // Replace the data of the player entity (including the items in its inventory) with
+    // player data taken from a different world
+    pub fn replace_player_data(&mut self, player: Entity, player_data: PlayerData) {
+        let PlayerData {
+            mut entity_data,
+            inventory_entity_data,
+        } = player_data;
+        let mut inventory = Inventory::new(inventory_entity_data.len());
+        for (slot, item_entity_data) in inventory.slots.iter_mut().zip(inventory_entity_data) {
+            if let Some(item_entity_data) = item_entity_data {
+                let item = self.entity_allocator.alloc();
+                self.components.insert_entity_data(item, item_entity_data);
+                *slot = Some(item);
+            }
+        }
+        entity_data.inventory = Some(inventory);
+        self.components.update_entity_data(player, entity_data);
+    }
+
+    pub fn maybe_move_character<R: Rng>(
         &mut self,
         character_entity: Entity,
         direction: CardinalDirection,
         message_log: &mut Vec<LogMessage>,
-    ) {
+        rng: &mut R,
+    ) -> Action {
         let character_coord = self
             .spatial_table
             .coord_of(character_entity)
@@ -239,33 +622,276 @@ impl World {
                 let dest_character_is_npc =
                     self.components.npc_type.get(dest_character_entity).cloned();
                 if character_is_npc.is_some() != dest_character_is_npc.is_some() {
-                    let victim_dies = self.character_bump_attack(dest_character_entity).is_some();
+                    let (damage, victim_dies) =
+                        self.character_attack(character_entity, dest_character_entity, rng);
                     let npc_type = character_is_npc.or(dest_character_is_npc).unwrap();
                     Self::write_combat_log_messages(
                         character_is_npc.is_none(),
-                        victim_dies,
+                        victim_dies.is_some(),
+                        damage,
                         npc_type,
                         message_log,
                     );
+                    if victim_dies.is_some() {
+                        self.maybe_gain_experience(
+                            character_entity,
+                            npc_type.experience_reward(),
+                            message_log,
+                        );
+                    }
+                    return Action::Attack;
+                }
+            } else {
+                let dest_feature = dest_layers
+                    .feature
+                    .map(|entity| (entity, self.components.tile.get(entity).cloned()));
+                match dest_feature {
+                    Some((door_entity, Some(Tile::ClosedDoor))) => {
+                        // Opening a door takes the character's turn, and leaves it where it is
+                        let npc_type = self.components.npc_type.get(character_entity).cloned();
+                        if npc_type.map(NpcType::can_open_doors).unwrap_or(true) {
+                            self.components.tile.insert(door_entity, Tile::OpenDoor);
+                            if npc_type.is_none() {
+                                message_log.push(LogMessage::PlayerOpensDoor);
+                            }
+                            return Action::OpenDoor;
+                        }
+                    }
+                    Some((_, Some(tile))) if tile.is_solid() => (),
+                    _ => {
+                        self.spatial_table
+                            .update_coord(character_entity, new_character_coord)
+                            .unwrap();
+                    }
                 }
-            } else if dest_layers.feature.is_none() {
-                self.spatial_table
-                    .update_coord(character_entity, new_character_coord)
-                    .unwrap();
             }
         }
+        // Trying to move somewhere blocked still uses up the character's turn
+        Action::Move
     }
 
-    fn character_bump_attack(&mut self, victim: Entity) -> Option<VictimDies> {
-        const DAMAGE: u32 = 1;
+    // The attacker shoots the victim from a distance. The caller is responsible for checking that
+    // nothing is in the way.
+    pub fn ranged_attack<R: Rng>(
+        &mut self,
+        attacker: Entity,
+        victim: Entity,
+        message_log: &mut Vec<LogMessage>,
+        rng: &mut R,
+    ) -> Action {
+        let npc_type = self
+            .npc_type(attacker)
+            .expect("only npcs have ranged attacks");
+        let (damage, victim_dies) = self.character_attack(attacker, victim, rng);
+        if victim_dies.is_some() {
+            message_log.push(LogMessage::NpcKillsPlayer(npc_type, damage));
+        } else {
+            message_log.push(LogMessage::NpcShootsPlayer(npc_type, damage));
+        }
+        Action::Attack
+    }
+
+    pub fn maybe_get_item(
+        &mut self,
+        character: Entity,
+        message_log: &mut Vec<LogMessage>,
+    ) -> Result<(), ()> {
+        let coord = self
+            .spatial_table
+            .coord_of(character)
+            .expect("character has no coord");
+        if let Some(object_entity) = self.spatial_table.layers_at_checked(coord).object {
+            if let Some(&item_type) = self.components.item.get(object_entity) {
+                // this assumes that the only character that can get items is the player
+                let inventory = self
+                    .components
+                    .inventory
+                    .get_mut(character)
+                    .expect("character has no inventory");
+                if inventory.insert(object_entity).is_ok() {
+                    // The item keeps its components but no longer has a location in the world
+                    self.spatial_table.remove(object_entity);
+                    message_log.push(LogMessage::PlayerGets(item_type));
+                    return Ok(());
+                } else {
+                    message_log.push(LogMessage::PlayerInventoryIsFull);
+                    return Err(());
+                }
+            }
+        }
+        message_log.push(LogMessage::NoItemUnderPlayer);
+        Err(())
+    }
+
+    pub fn maybe_use_item(
+        &mut self,
+        character: Entity,
+        inventory_index: usize,
+        message_log: &mut Vec<LogMessage>,
+    ) -> Result<(), ()> {
+        let inventory = self
+            .components
+            .inventory
+            .get_mut(character)
+            .expect("character has no inventory");
+        let item = match inventory.get(inventory_index) {
+            Ok(item) => item,
+            Err(InventorySlotIsEmpty) => return Err(()),
+        };
+        let &item_type = self
+            .components
+            .item
+            .get(item)
+            .expect("non-item in inventory");
+        match item_type {
+            ItemType::HealthPotion => {
+                let hit_points = self
+                    .components
+                    .hit_points
+                    .get_mut(character)
+                    .expect("character has no hit points");
+                if hit_points.current == hit_points.max {
+                    message_log.push(LogMessage::PlayerIsAtFullHealth);
+                    return Err(());
+                }
+                let heal = (hit_points.max / 2).min(hit_points.max - hit_points.current);
+                hit_points.current += heal;
+                message_log.push(LogMessage::PlayerHeals(heal));
+            }
+        }
+        inventory.remove(inventory_index).unwrap();
+        self.remove_entity(item);
+        Ok(())
+    }
+
+    pub fn maybe_drop_item(
+        &mut self,
+        character: Entity,
+        inventory_index: usize,
+        message_log: &mut Vec<LogMessage>,
+    ) -> Result<(), ()> {
+        let coord = self
+            .spatial_table
+            .coord_of(character)
+            .expect("character has no coord");
+        if self.spatial_table.layers_at_checked(coord).object.is_some() {
+            message_log.push(LogMessage::NoSpaceToDropItem);
+            return Err(());
+        }
+        let inventory = self
+            .components
+            .inventory
+            .get_mut(character)
+            .expect("character has no inventory");
+        let item = match inventory.remove(inventory_index) {
+            Ok(item) => item,
+            Err(InventorySlotIsEmpty) => return Err(()),
+        };
+        self.spatial_table
+            .update(
+                item,
+                Location {
+                    coord,
+                    layer: Some(Layer::Object),
+                },
+            )
+            .unwrap();
+        let &item_type = self
+            .components
+            .item
+            .get(item)
+            .expect("non-item in inventory");
+        message_log.push(LogMessage::PlayerDrops(item_type));
+        Ok(())
+    }
+
+    fn maybe_gain_experience(
+        &mut self,
+        character: Entity,
+        xp: u32,
+        message_log: &mut Vec<LogMessage>,
+    ) {
+        // Only characters with an experience component (i.e. the player) can gain experience
+        if let Some(experience) = self.components.experience.get_mut(character) {
+            if experience.gain(xp) > 0 {
+                message_log.push(LogMessage::PlayerLevelsUp(experience.level));
+            }
+        }
+    }
+
+    pub fn has_unspent_level_up(&self, character: Entity) -> bool {
+        self.components
+            .experience
+            .get(character)
+            .map(|experience| experience.unspent_level_ups > 0)
+            .unwrap_or(false)
+    }
+
+    pub fn level_up(&mut self, character: Entity, choice: LevelUpChoice) {
+        let experience = self
+            .components
+            .experience
+            .get_mut(character)
+            .expect("character has no experience");
+        if experience.unspent_level_ups == 0 {
+            return;
+        }
+        experience.unspent_level_ups -= 1;
+        match choice {
+            LevelUpChoice::MaxHitPoints => {
+                const MAX_HIT_POINTS_INCREASE: u32 = 5;
+                if let Some(hit_points) = self.components.hit_points.get_mut(character) {
+                    hit_points.max += MAX_HIT_POINTS_INCREASE;
+                    hit_points.current += MAX_HIT_POINTS_INCREASE;
+                }
+            }
+            LevelUpChoice::Attack => {
+                if let Some(attack) = self.components.attack.get_mut(character) {
+                    attack.0 += 1;
+                }
+            }
+            LevelUpChoice::Defence => {
+                if let Some(defence) = self.components.defence.get_mut(character) {
+                    defence.0 += 1;
+                }
+            }
+        }
+    }
+
+    // Damage is a random value between half the attacker's attack (rounded up) and its full
+    // attack, reduced by the victim's defence
+    fn roll_damage<R: Rng>(&self, attacker: Entity, victim: Entity, rng: &mut R) -> u32 {
+        let Attack(attack) = self
+            .components
+            .attack
+            .get(attacker)
+            .cloned()
+            .unwrap_or(Attack(0));
+        let Defence(defence) = self
+            .components
+            .defence
+            .get(victim)
+            .cloned()
+            .unwrap_or(Defence(0));
+        let roll = rng.gen_range(attack.div_ceil(2)..=attack);
+        roll.saturating_sub(defence)
+    }
+
+    fn character_attack<R: Rng>(
+        &mut self,
+        attacker: Entity,
+        victim: Entity,
+        rng: &mut R,
+    ) -> (u32, Option<VictimDies>) {
+        let damage = self.roll_damage(attacker, victim, rng);
         if let Some(hit_points) = self.components.hit_points.get_mut(victim) {
-            hit_points.current = hit_points.current.saturating_sub(DAMAGE);
+            hit_points.current = hit_points.current.saturating_sub(damage);
             if hit_points.current == 0 {
                 self.character_die(victim);
-                return Some(VictimDies);
+                return (damage, Some(VictimDies));
             }
         }
-        None
+        (damage, None)
     }
 
     fn character_die(&mut self, entity: Entity) {
@@ -275,12 +901,33 @@ impl World {
             .err()
             .map(|e| e.unwrap_occupied_by())
         {
-            // If a character dies on a cell which contains a corpse, remove the existing corpse
-            // from existence and replace it with the character's corpse.
-            self.remove_entity(occupied_by_entity);
-            self.spatial_table
-                .update_layer(entity, Layer::Object)
-                .unwrap();
+            if self.components.item.contains(occupied_by_entity) {
+                // If a character dies on a cell which contains an item, the item is moved out of
+                // the way of the character's corpse. If there is nowhere to move it to, the
+                // character leaves no corpse.
+                let coord = self.spatial_table.coord_of(entity).unwrap();
+                match self.nearest_coord_without_object(coord) {
+                    Some(item_coord) => {
+                        self.spatial_table
+                            .update_coord(occupied_by_entity, item_coord)
+                            .unwrap();
+                        self.spatial_table
+                            .update_layer(entity, Layer::Object)
+                            .unwrap();
+                    }
+                    None => {
+                        let location = Location { coord, layer: None };
+                        self.spatial_table.update(entity, location).unwrap();
+                    }
+                }
+            } else {
+                // If a character dies on a cell which contains a corpse, remove the existing
+                // corpse from existence and replace it with the character's corpse.
+                self.remove_entity(occupied_by_entity);
+                self.spatial_table
+                    .update_layer(entity, Layer::Object)
+                    .unwrap();
+            }
         }
         let current_tile = self.components.tile.get(entity).unwrap();
         let corpse_tile = match current_tile {
@@ -291,6 +938,72 @@ impl World {
         self.components.tile.insert(entity, corpse_tile);
     }
 
+    // Returns the closest cell to `coord` with nothing on its object layer, which can be reached
+    // from `coord` without passing through anything solid
+    fn nearest_coord_without_object(&self, coord: Coord) -> Option<Coord> {
+        let mut visited = Grid::new_copy(self.size(), false);
+        *visited.get_checked_mut(coord) = true;
+        let mut to_visit = VecDeque::new();
+        to_visit.push_back(coord);
+        while let Some(coord) = to_visit.pop_front() {
+            if self.spatial_table.layers_at_checked(coord).object.is_none() {
+                return Some(coord);
+            }
+            for direction in CardinalDirection::all() {
+                let neighbour = coord + direction.coord();
+                let can_pass = self
+                    .spatial_table
+                    .layers_at(neighbour)
+                    .is_some_and(|layers| layers.floor.is_some())
+                    && !self.feature_tile_at(neighbour).is_some_and(Tile::is_solid);
+                if can_pass && visited.get(neighbour) == Some(&false) {
+                    *visited.get_checked_mut(neighbour) = true;
+                    to_visit.push_back(neighbour);
+                }
+            }
+        }
+        None
+    }
+
+    pub fn is_stairs_at(&self, coord: Coord) -> bool {
+        self.spatial_table
+            .layers_at(coord)
+            .and_then(|layers| layers.floor)
+            .and_then(|entity| self.components.tile.get(entity))
+            .map(|&tile| matches!(tile, Tile::Stairs))
+            .unwrap_or(false)
+    }
+
+    pub fn energy(&self, entity: Entity) -> Option<i32> {
+        self.components
+            .energy
+            .get(entity)
+            .map(|&Energy(energy)| energy)
+    }
+
+    pub fn is_ready_to_act(&self, entity: Entity) -> bool {
+        self.energy(entity)
+            .is_some_and(|energy| energy >= ENERGY_TO_ACT)
+    }
+
+    pub fn spend_energy(&mut self, entity: Entity, action: Action) {
+        if let Some(Energy(energy)) = self.components.energy.get_mut(entity) {
+            *energy -= action.energy_cost();
+        }
+    }
+
+    // Advances the game clock by one tick, giving every living character energy according to
+    // its speed
+    pub fn tick(&mut self) {
+        for (entity, &Speed(speed)) in self.components.speed.iter() {
+            if self.spatial_table.layer_of(entity) == Some(Layer::Character) {
+                if let Some(Energy(energy)) = self.components.energy.get_mut(entity) {
+                    *energy += speed as i32;
+                }
+            }
+        }
+    }
+
     pub fn is_living_character(&self, entity: Entity) -> bool {
         self.spatial_table.layer_of(entity) == Some(Layer::Character)
     }
@@ -305,35 +1018,71 @@ impl World {
         self.spatial_table.grid_size()
     }
 
+    pub fn tile_at(&self, coord: Coord, layer: Layer) -> Option<Tile> {
+        let layers = self.spatial_table.layers_at(coord)?;
+        let entity = match layer {
+            Layer::Floor => layers.floor,
+            Layer::Character => layers.character,
+            Layer::Object => layers.object,
+            Layer::Feature => layers.feature,
+        }?;
+        self.components.tile.get(entity).cloned()
+    }
+
+    fn feature_tile_at(&self, coord: Coord) -> Option<Tile> {
+        self.tile_at(coord, Layer::Feature)
+    }
+
+    // Every light emitted by an entity that is in the world (rather than e.g. in an inventory),
+    // with the coordinate it is emitted from
+    pub fn lights<'a>(&'a self) -> impl 'a + Iterator<Item = (Coord, Light)> {
+        self.components
+            .light
+            .iter()
+            .filter_map(move |(entity, &light)| Some((self.spatial_table.coord_of(entity)?, light)))
+    }
+
     pub fn opacity_at(&self, coord: Coord) -> u8 {
-        if self
-            .spatial_table
-            .layers_at_checked(coord)
-            .feature
-            .is_some()
-        {
-            255
-        } else {
-            0
-        }
+        self.feature_tile_at(coord).map(Tile::opacity).unwrap_or(0)
     }
 
     pub fn hit_points(&self, entity: Entity) -> Option<HitPoints> {
         self.components.hit_points.get(entity).cloned()
     }
 
+    pub fn experience(&self, entity: Entity) -> Option<Experience> {
+        self.components.experience.get(entity).cloned()
+    }
+
+    pub fn inventory(&self, entity: Entity) -> Option<&Inventory> {
+        self.components.inventory.get(entity)
+    }
+
+    pub fn item_type(&self, entity: Entity) -> Option<ItemType> {
+        self.components.item.get(entity).cloned()
+    }
+
     pub fn entity_coord(&self, entity: Entity) -> Option<Coord> {
         self.spatial_table.coord_of(entity)
     }
 
+    pub fn npc_type(&self, entity: Entity) -> Option<NpcType> {
+        self.components.npc_type.get(entity).cloned()
+    }
+
+    // Returns true if some npcs could enter the cell, treating closed doors as passable as they
+    // can be opened by npcs that can open doors
     pub fn can_npc_enter_ignoring_other_npcs(&self, coord: Coord) -> bool {
-        self.spatial_table
-            .layers_at(coord)
-            .map(|layers| layers.feature.is_none())
-            .unwrap_or(false)
+        coord.is_valid(self.size())
+            && match self.feature_tile_at(coord) {
+                Some(Tile::ClosedDoor) => true,
+                Some(tile) => !tile.is_solid(),
+                None => true,
+            }
     }
 
-    pub fn can_npc_enter(&self, coord: Coord) -> bool {
+    // Returns true if an npc of the given type can enter the cell, or open a door in it
+    pub fn can_npc_enter(&self, coord: Coord, npc_type: NpcType) -> bool {
         self.spatial_table
             .layers_at(coord)
             .map(|layers| {
@@ -341,37 +1090,148 @@ impl World {
                     .character
                     .map(|entity| self.components.npc_type.contains(entity))
                     .unwrap_or(false);
-                let contains_feature = layers.feature.is_some();
-                !(contains_npc || contains_feature)
+                let can_pass_feature = match self.feature_tile_at(coord) {
+                    Some(Tile::ClosedDoor) => npc_type.can_open_doors(),
+                    Some(tile) => !tile.is_solid(),
+                    None => true,
+                };
+                !contains_npc && can_pass_feature
             })
             .unwrap_or(false)
     }
 
-    pub fn can_npc_see_through_cell(&self, coord: Coord) -> bool {
-        self.spatial_table
-            .layers_at(coord)
-            .map(|layers| layers.feature.is_none())
-            .unwrap_or(false)
+    // Returns true if a projectile could fly from one cell to another without hitting anything
+    // on the way. Cells that block movement also stop projectiles, even if they can be seen
+    // through.
+    pub fn is_projectile_path_clear(&self, src: Coord, dst: Coord) -> bool {
+        LineSegment::new(src, dst)
+            .iter()
+            .filter(|&coord| coord != src && coord != dst)
+            .all(|coord| {
+                let blocked_by_feature = self.feature_tile_at(coord).is_some_and(Tile::is_solid);
+                let blocked_by_character = self
+                    .spatial_table
+                    .layers_at(coord)
+                    .is_some_and(|layers| layers.character.is_some());
+                !blocked_by_feature && !blocked_by_character
+            })
     }
 
     fn write_combat_log_messages(
         attacker_is_player: bool,
         victim_dies: bool,
+        damage: u32,
         npc_type: NpcType,
         message_log: &mut Vec<LogMessage>,
     ) {
         if attacker_is_player {
             if victim_dies {
-                message_log.push(LogMessage::PlayerKillsNpc(npc_type));
+                message_log.push(LogMessage::PlayerKillsNpc(npc_type, damage));
             } else {
-                message_log.push(LogMessage::PlayerAttacksNpc(npc_type));
+                message_log.push(LogMessage::PlayerAttacksNpc(npc_type, damage));
             }
         } else {
             if victim_dies {
-                message_log.push(LogMessage::NpcKillsPlayer(npc_type));
+                message_log.push(LogMessage::NpcKillsPlayer(npc_type, damage));
             } else {
-                message_log.push(LogMessage::NpcAttacksPlayer(npc_type));
+                message_log.push(LogMessage::NpcAttacksPlayer(npc_type, damage));
             }
         }
     }
 }
+
+#[cfg(test)]
+impl World {
+    // Builds a world for tests from rows of text, see `terrain::parse_level` for the format
+    pub fn from_rows(rows: &[&str]) -> (Self, Populate) {
+        let terrain = terrain::parse_level(rows);
+        let mut world = World::new(terrain.size());
+        let populate = world.spawn_terrain(&terrain);
+        (world, populate)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_isaac::Isaac64Rng;
+
+    // A corridor running east from the player, with a closed door in the middle
+    fn corridor_with_door() -> (World, Entity, Coord) {
+        let (world, populate) = World::from_rows(&[".@+.."]);
+        (world, populate.player_entity, Coord::new(2, 0))
+    }
+
+    #[test]
+    fn bumping_a_closed_door_opens_it() {
+        let (mut world, player, door_coord) = corridor_with_door();
+        let mut message_log = Vec::new();
+        let mut rng = Isaac64Rng::seed_from_u64(0);
+        assert_eq!(world.opacity_at(door_coord), 255);
+        world.maybe_move_character(player, CardinalDirection::East, &mut message_log, &mut rng);
+        assert_eq!(world.entity_coord(player), Some(Coord::new(1, 0)));
+        assert_eq!(world.opacity_at(door_coord), 0);
+        assert_eq!(message_log, [LogMessage::PlayerOpensDoor]);
+        world.maybe_move_character(player, CardinalDirection::East, &mut message_log, &mut rng);
+        assert_eq!(world.entity_coord(player), Some(door_coord));
+    }
+
+    #[test]
+    fn only_some_npcs_can_open_doors() {
+        let (world, _, door_coord) = corridor_with_door();
+        assert!(world.can_npc_enter(door_coord, NpcType::Orc));
+        assert!(!world.can_npc_enter(door_coord, NpcType::Troll));
+        assert!(world.can_npc_enter_ignoring_other_npcs(door_coord));
+    }
+
+    #[test]
+    fn killing_blows_report_their_damage() {
+        let (mut world, populate) = World::from_rows(&["@o"]);
+        let player = populate.player_entity;
+        let orc = populate.ai_state.entities().next().unwrap();
+        world
+            .components
+            .hit_points
+            .insert(orc, HitPoints { current: 1, max: 4 });
+        let mut message_log = Vec::new();
+        let mut rng = Isaac64Rng::seed_from_u64(0);
+        world.maybe_move_character(player, CardinalDirection::East, &mut message_log, &mut rng);
+        assert!(matches!(
+            message_log[..],
+            [LogMessage::PlayerKillsNpc(NpcType::Orc, damage)] if damage >= 1
+        ));
+    }
+
+    #[test]
+    fn npcs_dying_on_an_item_leave_it_next_to_their_corpse() {
+        let (mut world, populate) = World::from_rows(&["@o.", "###"]);
+        let player = populate.player_entity;
+        let orc = populate.ai_state.entities().next().unwrap();
+        let orc_coord = Coord::new(1, 0);
+        world.spawn_item(orc_coord, ItemType::HealthPotion);
+        world
+            .components
+            .hit_points
+            .insert(orc, HitPoints { current: 1, max: 4 });
+        let mut rng = Isaac64Rng::seed_from_u64(0);
+        world.maybe_move_character(player, CardinalDirection::East, &mut Vec::new(), &mut rng);
+        assert!(!world.is_living_character(orc));
+        let object_at = |coord| world.spatial_table.layers_at_checked(coord).object;
+        assert_eq!(object_at(orc_coord), Some(orc));
+        let potion = object_at(Coord::new(2, 0)).unwrap();
+        assert_eq!(world.item_type(potion), Some(ItemType::HealthPotion));
+    }
+
+    #[test]
+    fn glass_blocks_movement_but_not_sight() {
+        let (mut world, populate) = World::from_rows(&["@=."]);
+        let player = populate.player_entity;
+        let glass_coord = Coord::new(1, 0);
+        let mut rng = Isaac64Rng::seed_from_u64(0);
+        world.maybe_move_character(player, CardinalDirection::East, &mut Vec::new(), &mut rng);
+        assert_eq!(world.entity_coord(player), Some(Coord::new(0, 0)));
+        assert_eq!(world.opacity_at(glass_coord), 0);
+        assert!(!world.can_npc_enter(glass_coord, NpcType::Orc));
+    }
+}
@@ -0,0 +1,208 @@
+use coord_2d::{Coord, Size};
+use direction::CardinalDirection;
+use rustoguelike::game::{GameAction, LogMessage};
+use rustoguelike::headless::{Observation, Simulation};
+use rustoguelike::terrain::GeneratorChoice;
+use rustoguelike::visibility::VisibilityAlgorithm;
+
+fn new_simulation(rng_seed: u64) -> Simulation {
+    Simulation::new(
+        Size::new(40, 25),
+        rng_seed,
+        GeneratorChoice::default(),
+        VisibilityAlgorithm::Shadowcast,
+    )
+}
+
+// Steps towards the nearest NPC, so the player ends up fighting. This is deliberately simple and
+// will often walk into walls, which is fine as the NPCs come to the player once they see them.
+fn chase_nearest_npc(observation: &Observation) -> GameAction {
+    let player_coord = observation.player_coord;
+    let nearest_npc_coord = observation
+        .npcs
+        .iter()
+        .map(|npc| npc.coord)
+        .min_by_key(|&coord| coord.distance2(player_coord));
+    let delta = match nearest_npc_coord {
+        Some(coord) => coord - player_coord,
+        None => return GameAction::Wait,
+    };
+    let direction = if delta.x.abs() > delta.y.abs() {
+        if delta.x > 0 {
+            CardinalDirection::East
+        } else {
+            CardinalDirection::West
+        }
+    } else if delta.y > 0 {
+        CardinalDirection::South
+    } else {
+        CardinalDirection::North
+    };
+    GameAction::Move(direction)
+}
+
+// Plays a game chasing NPCs and returns the observation before and after every action
+fn chase_npcs(rng_seed: u64, num_actions: usize) -> Vec<(Observation, Observation)> {
+    let mut simulation = new_simulation(rng_seed);
+    let mut observation = simulation.observe();
+    let mut transitions = Vec::new();
+    for _ in 0..num_actions {
+        let next_observation = simulation.step(chase_nearest_npc(&observation));
+        transitions.push((observation, next_observation.clone()));
+        if !next_observation.is_player_alive {
+            break;
+        }
+        observation = next_observation;
+    }
+    transitions
+}
+
+fn is_adjacent(a: Coord, b: Coord) -> bool {
+    a.manhattan_distance(b) == 1
+}
+
+#[test]
+fn same_seed_and_actions_give_same_observations() {
+    let actions = [
+        GameAction::Move(CardinalDirection::North),
+        GameAction::Move(CardinalDirection::East),
+        GameAction::Wait,
+        GameAction::GetItem,
+        GameAction::Move(CardinalDirection::South),
+        GameAction::Move(CardinalDirection::West),
+    ];
+    for rng_seed in 0..10 {
+        let a = new_simulation(rng_seed).run(actions.iter().cloned().cycle().take(200));
+        let b = new_simulation(rng_seed).run(actions.iter().cloned().cycle().take(200));
+        assert_eq!(a, b);
+    }
+}
+
+#[test]
+fn new_game_starts_with_player_visible_and_at_full_health() {
+    for rng_seed in 0..10 {
+        let observation = new_simulation(rng_seed).observe();
+        assert!(observation.is_player_alive);
+        assert_eq!(
+            observation.player_hit_points.current,
+            observation.player_hit_points.max
+        );
+        assert_eq!(observation.dungeon_level, 1);
+        assert!(observation
+            .visible_cells
+            .contains(&observation.player_coord));
+        assert!(observation.messages.is_empty());
+    }
+}
+
+#[test]
+fn characters_move_a_bounded_distance_per_action() {
+    for rng_seed in 0..10 {
+        for (before, after) in chase_npcs(rng_seed, 200) {
+            assert!(
+                after.player_coord == before.player_coord
+                    || is_adjacent(after.player_coord, before.player_coord)
+            );
+            assert!(after.visible_cells.contains(&after.player_coord));
+            // NPCs are listed in a stable order, so while no character dies they can be compared
+            // in turn. (A character dying on a corpse removes the corpse, which can reorder the
+            // list.) No NPC is faster than the player, but an NPC can still move twice while the
+            // player makes an attack, which takes longer than a move.
+            if after.is_player_alive && before.npcs.len() == after.npcs.len() {
+                for (npc_before, npc_after) in before.npcs.iter().zip(after.npcs.iter()) {
+                    assert_eq!(npc_before.npc_type, npc_after.npc_type);
+                    assert!(npc_after.coord.manhattan_distance(npc_before.coord) <= 2);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn npcs_take_a_turn_after_each_player_action() {
+    let npc_moved = (0..10).any(|rng_seed| {
+        let mut simulation = new_simulation(rng_seed);
+        let before = simulation.observe();
+        (0..20).any(|_| {
+            let after = simulation.step(GameAction::Wait);
+            after.npcs != before.npcs
+        })
+    });
+    assert!(npc_moved, "no NPC moved while the player waited");
+}
+
+#[test]
+fn player_loses_the_damage_reported_in_the_log() {
+    let mut num_attacks = 0;
+    for rng_seed in 0..20 {
+        for (before, after) in chase_npcs(rng_seed, 200) {
+            let damage: u32 = after
+                .messages
+                .iter()
+                .filter_map(|message| match message {
+                    LogMessage::NpcAttacksPlayer(_, damage)
+                    | LogMessage::NpcShootsPlayer(_, damage) => Some(damage),
+                    _ => None,
+                })
+                .sum();
+            if damage > 0 {
+                num_attacks += 1;
+            }
+            let expected_hit_points = if after.is_player_alive {
+                before.player_hit_points.current - damage
+            } else {
+                0
+            };
+            assert_eq!(after.player_hit_points.current, expected_hit_points);
+        }
+    }
+    assert!(num_attacks > 0, "no NPC attacked the player");
+}
+
+#[test]
+fn killed_npcs_are_removed() {
+    let mut num_kills = 0;
+    for rng_seed in 0..20 {
+        for (before, after) in chase_npcs(rng_seed, 200) {
+            let kills = after
+                .messages
+                .iter()
+                .filter(|message| matches!(message, LogMessage::PlayerKillsNpc(..)))
+                .count();
+            num_kills += kills;
+            assert_eq!(after.npcs.len(), before.npcs.len() - kills);
+            // The player can only attack an NPC standing next to them
+            if kills > 0 {
+                assert!(before
+                    .npcs
+                    .iter()
+                    .any(|npc| is_adjacent(npc.coord, before.player_coord)));
+            }
+        }
+    }
+    assert!(num_kills > 0, "the player never killed an NPC");
+}
+
+#[test]
+fn actions_after_death_are_ignored() {
+    let dead_game = (0..50).find_map(|rng_seed| {
+        let transitions = chase_npcs(rng_seed, 500);
+        let (_, last) = transitions.last()?;
+        if last.is_player_alive {
+            None
+        } else {
+            Some((rng_seed, transitions.len()))
+        }
+    });
+    let (rng_seed, num_actions) = dead_game.expect("the player never died");
+    let mut simulation = new_simulation(rng_seed);
+    let mut observation = simulation.observe();
+    for _ in 0..num_actions {
+        observation = simulation.step(chase_nearest_npc(&observation));
+    }
+    assert!(!observation.is_player_alive);
+    let after_death = simulation.step(GameAction::Move(CardinalDirection::North));
+    assert!(after_death.messages.is_empty());
+    assert_eq!(after_death.player_coord, observation.player_coord);
+    assert_eq!(after_death.npcs, observation.npcs);
+}
@@ -0,0 +1,148 @@
+// Golden-file tests of what the player sees. Each test renders the screen as text and compares
+// it with a file in tests/snapshots. After an intentional change to what is drawn, regenerate
+// the files with:
+//
+//     UPDATE_SNAPSHOTS=1 cargo test --test snapshots
+//
+// and check the diff before committing.
+
+use coord_2d::Size;
+use direction::CardinalDirection;
+use rustoguelike::app::{self, render_text};
+use rustoguelike::game::GameAction;
+use rustoguelike::headless::Simulation;
+use rustoguelike::terrain::{GeneratorChoice, GeneratorKind};
+use rustoguelike::visibility::VisibilityAlgorithm;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+fn screen_size() -> Size {
+    Size::new(40, 30)
+}
+
+fn new_simulation(rng_seed: u64, visibility_algorithm: VisibilityAlgorithm) -> Simulation {
+    new_simulation_with_generator(rng_seed, GeneratorChoice::default(), visibility_algorithm)
+}
+
+fn new_simulation_with_generator(
+    rng_seed: u64,
+    generator_choice: GeneratorChoice,
+    visibility_algorithm: VisibilityAlgorithm,
+) -> Simulation {
+    Simulation::new(
+        app::game_area_size(screen_size()),
+        rng_seed,
+        generator_choice,
+        visibility_algorithm,
+    )
+}
+
+fn assert_snapshot(name: &str, actual: &str) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("snapshots")
+        .join(format!("{}.txt", name));
+    if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path).unwrap_or_else(|error| {
+        panic!(
+            "failed to read {} ({}), run with UPDATE_SNAPSHOTS=1 to create it",
+            path.display(),
+            error
+        )
+    });
+    if expected == actual {
+        return;
+    }
+    let mut diff = String::new();
+    let expected_lines = expected.lines().map(Some).chain(std::iter::repeat(None));
+    let actual_lines = actual.lines().map(Some).chain(std::iter::repeat(None));
+    for (expected_line, actual_line) in expected_lines.zip(actual_lines) {
+        match (expected_line, actual_line) {
+            (None, None) => break,
+            (Some(e), Some(a)) if e == a => diff.push_str(&format!(" |{}\n", e)),
+            (e, a) => {
+                if let Some(e) = e {
+                    diff.push_str(&format!("-|{}\n", e));
+                }
+                if let Some(a) = a {
+                    diff.push_str(&format!("+|{}\n", a));
+                }
+            }
+        }
+    }
+    panic!(
+        "screen differs from {} (- expected, + actual):\n{}",
+        path.display(),
+        diff
+    );
+}
+
+#[test]
+fn new_game() {
+    for rng_seed in 0..3 {
+        let simulation = new_simulation(rng_seed, VisibilityAlgorithm::Shadowcast);
+        assert_snapshot(
+            &format!("new_game_seed_{}", rng_seed),
+            &render_text(simulation.game_state(), screen_size()),
+        );
+    }
+}
+
+#[test]
+fn whole_level_omniscient() {
+    for rng_seed in 0..3 {
+        let simulation = new_simulation(rng_seed, VisibilityAlgorithm::Omniscient);
+        assert_snapshot(
+            &format!("whole_level_seed_{}", rng_seed),
+            &render_text(simulation.game_state(), screen_size()),
+        );
+    }
+}
+
+#[test]
+fn whole_level_for_each_generator() {
+    for &kind in GeneratorKind::ALL.iter() {
+        for rng_seed in 0..2 {
+            let simulation = new_simulation_with_generator(
+                rng_seed,
+                GeneratorChoice::Always(kind),
+                VisibilityAlgorithm::Omniscient,
+            );
+            assert_snapshot(
+                &format!(
+                    "generator_{}_seed_{}",
+                    GeneratorChoice::Always(kind),
+                    rng_seed
+                ),
+                &render_text(simulation.game_state(), screen_size()),
+            );
+        }
+    }
+}
+
+// Walks around so that some cells are only remembered and some messages are logged
+#[test]
+fn after_exploring() {
+    let moves = [
+        (CardinalDirection::East, 8),
+        (CardinalDirection::South, 6),
+        (CardinalDirection::West, 12),
+        (CardinalDirection::North, 10),
+        (CardinalDirection::East, 4),
+    ];
+    for rng_seed in 0..3 {
+        let mut simulation = new_simulation(rng_seed, VisibilityAlgorithm::Shadowcast);
+        for &(direction, count) in moves.iter() {
+            simulation.run((0..count).map(|_| GameAction::Move(direction)));
+        }
+        assert_snapshot(
+            &format!("after_exploring_seed_{}", rng_seed),
+            &render_text(simulation.game_state(), screen_size()),
+        );
+    }
+}